@@ -1,17 +1,27 @@
+pub mod reload;
+pub mod supervisor;
+
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use anyhow::Result;
 use once_cell::sync::Lazy;
-use tokio::{sync::mpsc, task};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch, Mutex},
+    task::{self, JoinHandle},
+    time::timeout,
+};
 
 use crate::{
     balance_checker::BalanceChecker,
-    chains::chains::{make_l2_provider, make_providers, ChainProviders},
+    chains::chains::{make_l2_provider, make_providers, ChainProviders, L1Set, L1State},
     config::Config,
-    database::db::DB,
+    database::{db::DB, pruner::Pruner},
     error::AggregatorError,
     json_rpc_server::server::JsonRpcServer,
     poster::poster::Poster,
@@ -19,11 +29,17 @@ use crate::{
     verifier::{sp1::SP1, verifier::Verifier},
 };
 
+use self::{
+    reload::Reloader,
+    supervisor::{supervise, Health},
+};
+
 pub static ELF_CONFIG: Lazy<RwLock<HashMap<String, String>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
-pub async fn run(cfg: Config) -> Result<()> {
+pub async fn run(cfg: Config, config_path: PathBuf) -> Result<()> {
     tracing::info!("Starting twine aggregator");
+    let running_config = cfg.clone();
 
     let provers: HashMap<String, String> = cfg
         .provers
@@ -40,10 +56,20 @@ pub async fn run(cfg: Config) -> Result<()> {
     // Post status saved to db
     let (post_status_tx, post_status_rx) = mpsc::channel(100);
 
+    // Config reloads requested over the admin RPC
+    let (reload_tx, reload_rx) = mpsc::channel(10);
+
+    // Set on SIGINT or SIGTERM, stops the server so the rest of the pipeline drains
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
     let port = cfg.global.server_port;
-    let db_path = cfg.global.db_path;
+    let db_path = cfg.global.db_path.expose().to_string();
+    let blob_path = cfg.global.blob_path.clone();
     let threshold = cfg.global.threshold;
     let balance_check_interval = cfg.global.balance_check_interval;
+    let start_height = cfg.global.start_height;
+    let retention = cfg.retention;
+    let shutdown_timeout = Duration::from_secs(cfg.global.shutdown_timeout);
     let l1s = cfg.l1s;
     let l2 = cfg.l2;
 
@@ -54,68 +80,255 @@ pub async fn run(cfg: Config) -> Result<()> {
         *elf_config = elfs;
     }
 
-    let providers: HashMap<String, ChainProviders> = make_providers(l1s.clone());
+    let providers: HashMap<String, ChainProviders> = make_providers(l1s.clone()).await?;
     let balance_threshold: HashMap<String, String> = make_threshold_map(l1s);
 
-    let balance_checker =
-        BalanceChecker::new(providers.clone(), balance_threshold, balance_check_interval);
+    // L1s are replaced as a whole when the config is reloaded
+    let (l1s_tx, l1s_rx) = watch::channel(L1Set {
+        providers: providers.clone(),
+        balance_thresholds: balance_threshold,
+    });
+
+    let balance_checker = BalanceChecker::new(l1s_rx.clone(), balance_check_interval);
 
-    let proof_receiver = JsonRpcServer::new(provers, verifier_tx);
-    let db_arc = Arc::new(DB::new(threshold, db_path).await);
+    // Component status, served over the `twarb_health` RPC
+    let health = Health::default();
 
-    let mut verifier = Verifier::new(verifier_rx, Arc::clone(&db_arc));
+    let provers = Arc::new(RwLock::new(provers));
+    let db_arc = Arc::new(DB::new(threshold, db_path, blob_path).await);
+    let proof_receiver = JsonRpcServer::new(
+        Arc::clone(&provers),
+        verifier_tx,
+        reload_tx,
+        health.clone(),
+        Arc::clone(&db_arc),
+    );
+
+    // Contract state at the final L1 block is the source of truth for what has been settled.
+    // Batches finalized on the contract in blocks that can still reorg stay unposted until a later start.
+    for (chain, provider) in &providers {
+        match provider.settled_batch().await {
+            Ok(last_finalized) => {
+                if let Err(e) = db_arc.reconcile_l1_status(chain, last_finalized).await {
+                    tracing::error!(
+                        "Failed to reconcile post status. chain:{} error:{}",
+                        chain,
+                        e
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read contract state. chain:{} error:{}", chain, e);
+            }
+        }
+    }
+
+    // Batches verified before the last shutdown that did not reach every chain
+    let chains: Vec<String> = providers.keys().cloned().collect();
+    let unposted = match db_arc.unposted_batches(&chains).await {
+        Ok(batches) => batches,
+        Err(e) => {
+            tracing::error!("Failed to load unposted batches. error:{}", e);
+            vec![]
+        }
+    };
+
+    let verifier = Arc::new(Mutex::new(Verifier::new(verifier_rx, Arc::clone(&db_arc))));
+
+    let pruner = Arc::new(Pruner::new(Arc::clone(&db_arc), retention, l1s_rx.clone()));
+
+    let reloader = Arc::new(Mutex::new(Reloader::new(
+        config_path,
+        running_config,
+        provers,
+        Arc::clone(&db_arc),
+        l1s_tx,
+    )));
 
     let mut poster = Poster::new(
-        providers,
+        l1s_rx,
         post_status_tx,
-        make_l2_provider(l2),
-    );
+        ChainProviders::EVM(make_l2_provider(l2).await),
+        start_height,
+    )
+    .with_health(health.clone());
+    poster.restore(unposted);
 
-    let server_task = task::spawn(async move {
-        proof_receiver
-            .run_server(port)
-            .await
-            .map_err(|e| AggregatorError::JsonRPCServerError(e.to_string()))
-    });
+    // State lives outside the tasks, so a restarted component picks up where it failed
+    let poster = Arc::new(Mutex::new(poster));
+    let poster_rx = Arc::new(Mutex::new(poster_rx));
+    let post_status_rx = Arc::new(Mutex::new(post_status_rx));
+    let reload_rx = Arc::new(Mutex::new(reload_rx));
+    let balance_checker = Arc::new(balance_checker);
 
-    let sp1 = SP1::new().await;
+    let server_task = task::spawn(supervise("server", health.clone(), move || {
+        let proof_receiver = proof_receiver.clone();
+        let shutdown_rx = shutdown_rx.clone();
+        async move {
+            proof_receiver
+                .run_server(port, shutdown_rx)
+                .await
+                .map_err(|e| AggregatorError::JsonRPCServerError(e.to_string()))
+        }
+    }));
 
-    let validator_task = task::spawn(async move {
-        verifier
-            .run(sp1, poster_tx)
-            .await
-            .map_err(|e| AggregatorError::Custom(e.to_string()))
-    });
+    let sp1 = Arc::new(SP1::new().await);
 
-    let db_clone = Arc::clone(&db_arc);
-    let db_task = tokio::spawn(async move {
-        db_clone
-            .run(post_status_rx)
-            .await
-            .map_err(|e| AggregatorError::DBError(e.to_string()))
-    });
+    let validator_task = task::spawn(supervise("verifier", health.clone(), move || {
+        let verifier = Arc::clone(&verifier);
+        let sp1 = Arc::clone(&sp1);
+        let poster_tx = poster_tx.clone();
+        async move {
+            verifier
+                .lock()
+                .await
+                .run(Some(sp1.as_ref()), poster_tx)
+                .await
+                .map_err(|e| AggregatorError::Custom(e.to_string()))
+        }
+    }));
 
-    let poster_task = task::spawn(async move {
-        poster
-            .run(poster_rx)
-            .await
-            .map_err(|e| AggregatorError::PosterError(e.to_string()))
-    });
+    let db_task = task::spawn(supervise("database", health.clone(), move || {
+        let db = Arc::clone(&db_arc);
+        let post_status_rx = Arc::clone(&post_status_rx);
+        async move {
+            db.run(&mut *post_status_rx.lock().await)
+                .await
+                .map_err(|e| AggregatorError::DBError(e.to_string()))
+        }
+    }));
 
-    let balance_check_task = task::spawn(async move {
-        balance_checker
-            .run()
-            .await
-            .expect("Failed to run balance checker");
-    });
+    let poster_task = task::spawn(supervise("poster", health.clone(), move || {
+        let poster = Arc::clone(&poster);
+        let poster_rx = Arc::clone(&poster_rx);
+        async move {
+            poster
+                .lock()
+                .await
+                .run(&mut *poster_rx.lock().await)
+                .await
+                .map_err(|e| AggregatorError::PosterError(e.to_string()))
+        }
+    }));
 
-    let _ = tokio::try_join!(
-        server_task,
-        validator_task,
-        poster_task,
-        balance_check_task,
-        db_task
-    );
+    let balance_check_task = task::spawn(supervise("balance_checker", health.clone(), move || {
+        let balance_checker = Arc::clone(&balance_checker);
+        async move {
+            balance_checker
+                .run()
+                .await
+                .map_err(|e| AggregatorError::Custom(e.to_string()))
+        }
+    }));
+
+    let prune_task = task::spawn(supervise("pruner", health.clone(), move || {
+        let pruner = Arc::clone(&pruner);
+        async move {
+            pruner
+                .run()
+                .await
+                .map_err(|e| AggregatorError::DBError(e.to_string()))
+        }
+    }));
+
+    let reload_task = task::spawn(supervise("reloader", health.clone(), move || {
+        let reloader = Arc::clone(&reloader);
+        let reload_rx = Arc::clone(&reload_rx);
+        async move {
+            reloader
+                .lock()
+                .await
+                .run(&mut *reload_rx.lock().await)
+                .await
+                .map_err(|e| AggregatorError::Custom(e.to_string()))
+        }
+    }));
+
+    let background = [
+        balance_check_task.abort_handle(),
+        prune_task.abort_handle(),
+        reload_task.abort_handle(),
+    ];
+    let background_tasks = async {
+        tokio::try_join!(
+            joined(balance_check_task),
+            joined(prune_task),
+            joined(reload_task)
+        )
+    };
+    tokio::pin!(background_tasks);
+
+    // Each stage ends once the one before it is gone: server, verifier, poster, then the DB
+    let pipeline = async {
+        tokio::try_join!(
+            joined(server_task),
+            joined(validator_task),
+            joined(poster_task),
+            joined(db_task)
+        )
+    };
+    tokio::pin!(pipeline);
+
+    let result = tokio::select! {
+        signal = shutdown_signal() => {
+            tracing::info!(
+                "{} received, draining pipeline. timeout:{}s",
+                signal?,
+                shutdown_timeout.as_secs()
+            );
+            let _ = shutdown_tx.send(true);
+            match timeout(shutdown_timeout, &mut pipeline).await {
+                Ok(Ok(_)) => {
+                    tracing::info!("Pipeline drained");
+                    Ok(())
+                }
+                Ok(Err(e)) => Err(e),
+                Err(_) => {
+                    tracing::warn!(
+                        "Pipeline did not drain in time, unposted batches resume from the DB on restart"
+                    );
+                    Ok(())
+                }
+            }
+        }
+        result = &mut pipeline => result.map(|_| {
+            tracing::warn!("Pipeline stopped without a shutdown signal");
+        }),
+        Err(e) = &mut background_tasks => Err(e),
+    };
+
+    for task in background {
+        task.abort();
+    }
+    match result {
+        Ok(()) => {
+            tracing::info!("Twine aggregator stopped");
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!(
+                "Twine aggregator stopping, a component keeps failing. error:{}",
+                e
+            );
+            Err(e.into())
+        }
+    }
+}
+
+/// Resolves with the name of the first SIGINT or SIGTERM
+async fn shutdown_signal() -> Result<&'static str> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            Ok("SIGINT")
+        }
+        _ = terminate.recv() => Ok("SIGTERM"),
+    }
+}
 
-    Ok(())
+/// Result of a supervised task, with a failure of the supervisor itself counted as an error
+async fn joined(task: JoinHandle<Result<(), AggregatorError>>) -> Result<(), AggregatorError> {
+    task.await
+        .map_err(|e| AggregatorError::Custom(e.to_string()))?
 }
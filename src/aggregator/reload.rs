@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc::Receiver, oneshot, watch},
+};
+
+use crate::{
+    chains::chains::{make_provider, L1Set},
+    cmd::cfg::load_and_validate_config,
+    config::{Config, ProverDetails},
+    database::db::DB,
+};
+
+/// Asks for a config reload. The reply holds the applied changes, or why the reload failed.
+pub type ReloadRequest = oneshot::Sender<Result<Vec<String>, String>>;
+
+/// Re-reads the config on SIGHUP or the `twarb_reloadConfig` admin RPC and applies the parts
+/// that can change while running: the prover allowlist, the threshold and the L1s.
+pub struct Reloader {
+    config_path: PathBuf,
+    current: Config,
+    provers: Arc<RwLock<HashMap<String, String>>>,
+    db: Arc<DB>,
+    l1s: watch::Sender<L1Set>,
+}
+
+impl Reloader {
+    pub fn new(
+        config_path: PathBuf,
+        current: Config,
+        provers: Arc<RwLock<HashMap<String, String>>>,
+        db: Arc<DB>,
+        l1s: watch::Sender<L1Set>,
+    ) -> Self {
+        Self {
+            config_path,
+            current,
+            provers,
+            db,
+            l1s,
+        }
+    }
+
+    pub async fn run(&mut self, requests: &mut Receiver<ReloadRequest>) -> Result<()> {
+        tracing::info!("Config reload service running");
+        let mut hangup = signal(SignalKind::hangup())?;
+        loop {
+            tokio::select! {
+                Some(()) = hangup.recv() => {
+                    tracing::info!("SIGHUP received, reloading config");
+                    let _ = self.reload().await;
+                }
+                request = requests.recv() => {
+                    let Some(reply) = request else {
+                        break;
+                    };
+                    let result = self.reload().await.map_err(|e| format!("{:#}", e));
+                    let _ = reply.send(result);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and validates the config file and applies what changed.
+    /// Nothing is applied if the new config is invalid or a new provider cannot be built.
+    pub async fn reload(&mut self) -> Result<Vec<String>> {
+        let result = self.apply().await;
+        match &result {
+            Ok(changes) if changes.is_empty() => tracing::info!("Config reloaded, nothing changed"),
+            Ok(changes) => {
+                for change in changes {
+                    tracing::info!("Config change applied. {}", change);
+                }
+            }
+            Err(e) => tracing::error!(
+                "Config reload failed, keeping current config. error:{:#}",
+                e
+            ),
+        }
+        result
+    }
+
+    async fn apply(&mut self) -> Result<Vec<String>> {
+        let (new, _) = load_and_validate_config(self.config_path.clone())?;
+        let mut changes = vec![];
+
+        // built before anything is applied, so a failing provider leaves the running config untouched
+        let l1s = self.next_l1s(&new, &mut changes).await?;
+
+        prover_changes(&self.current.provers, &new.provers, &mut changes);
+        *self.provers.write().unwrap() = new
+            .provers
+            .iter()
+            .map(|(id, details)| (id.clone(), details.prover_type.clone()))
+            .collect();
+
+        if new.global.threshold != self.current.global.threshold {
+            changes.push(format!(
+                "threshold: {} -> {}",
+                self.current.global.threshold, new.global.threshold
+            ));
+            self.db.set_threshold(new.global.threshold);
+        }
+
+        if let Some(l1s) = l1s {
+            self.l1s.send_replace(l1s);
+        }
+
+        self.warn_restart_only(&new);
+        self.current = new;
+        Ok(changes)
+    }
+
+    /// The L1 set of the new config, or None if no L1 changed. Unchanged chains keep their provider,
+    /// so their transaction manager state carries over.
+    async fn next_l1s(&self, new: &Config, changes: &mut Vec<String>) -> Result<Option<L1Set>> {
+        let current = self.l1s.borrow().clone();
+        let mut providers = HashMap::new();
+        let mut changed = false;
+
+        for (chain, details) in &new.l1s {
+            let old = self.current.l1s.get(chain);
+            if old == Some(details) {
+                if let Some(provider) = current.providers.get(chain) {
+                    providers.insert(chain.clone(), provider.clone());
+                    continue;
+                }
+            }
+            let provider = make_provider(details)
+                .await
+                .with_context(|| format!("Failed building provider for {}", chain))?;
+            providers.insert(chain.clone(), provider);
+            changed = true;
+            changes.push(match old {
+                Some(_) => format!("l1 changed: {}", chain),
+                None => format!("l1 added: {}", chain),
+            });
+        }
+        for chain in self.current.l1s.keys() {
+            if !new.l1s.contains_key(chain) {
+                changed = true;
+                changes.push(format!("l1 removed: {}", chain));
+            }
+        }
+
+        if !changed {
+            return Ok(None);
+        }
+        Ok(Some(L1Set {
+            providers,
+            balance_thresholds: new
+                .l1s
+                .iter()
+                .map(|(chain, details)| (chain.clone(), details.get_balance_threshold()))
+                .collect(),
+        }))
+    }
+
+    fn warn_restart_only(&self, new: &Config) {
+        let old = &self.current;
+        let restart_only = [
+            (
+                "global.server_port",
+                old.global.server_port != new.global.server_port,
+            ),
+            ("global.db_path", old.global.db_path != new.global.db_path),
+            (
+                "global.blob_path",
+                old.global.blob_path != new.global.blob_path,
+            ),
+            ("global.logging", old.global.logging != new.global.logging),
+            (
+                "global.start_height",
+                old.global.start_height != new.global.start_height,
+            ),
+            (
+                "global.balance_check_interval",
+                old.global.balance_check_interval != new.global.balance_check_interval,
+            ),
+            (
+                "global.shutdown_timeout",
+                old.global.shutdown_timeout != new.global.shutdown_timeout,
+            ),
+            ("elf", old.elf != new.elf),
+            ("l2", old.l2 != new.l2),
+            ("retention", old.retention != new.retention),
+        ];
+        for (field, changed) in restart_only {
+            if changed {
+                tracing::warn!("Config change needs a restart to apply. field:{}", field);
+            }
+        }
+    }
+}
+
+fn prover_changes(
+    old: &HashMap<String, ProverDetails>,
+    new: &HashMap<String, ProverDetails>,
+    changes: &mut Vec<String>,
+) {
+    for (id, details) in new {
+        match old.get(id) {
+            None => changes.push(format!("prover added: {} ({})", id, details.prover_type)),
+            Some(old_details) if old_details != details => {
+                changes.push(format!("prover changed: {}", id))
+            }
+            Some(_) => {}
+        }
+    }
+    for id in old.keys() {
+        if !new.contains_key(id) {
+            changes.push(format!("prover removed: {}", id));
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use tokio::{
+    task::JoinSet,
+    time::{sleep, Instant},
+};
+
+use crate::error::AggregatorError;
+
+/// A component that fails this many times within `FAILURE_WINDOW` is given up on
+static MAX_FAILURES: usize = 5;
+static FAILURE_WINDOW: Duration = Duration::from_secs(300);
+static INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+static MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    /// Failed and waiting to be restarted
+    Restarting {
+        failures: usize,
+        error: String,
+    },
+    /// Failed too often, the aggregator is shutting down
+    Failed(String),
+    Stopped,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Running => write!(f, "running"),
+            Status::Restarting { failures, error } => {
+                write!(
+                    f,
+                    "restarting after {} failures, last error: {}",
+                    failures, error
+                )
+            }
+            Status::Failed(e) => write!(f, "failed: {}", e),
+            Status::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+/// Status of every supervised component and the alerts raised by them, shared with the
+/// `twarb_health` RPC
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    components: Arc<RwLock<BTreeMap<&'static str, Status>>>,
+    /// Problems that need an operator, by what they are about
+    alerts: Arc<RwLock<BTreeMap<String, String>>>,
+}
+
+impl Health {
+    fn set(&self, component: &'static str, status: Status) {
+        self.components.write().unwrap().insert(component, status);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, String> {
+        self.components
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(component, status)| (component.to_string(), status.to_string()))
+            .collect()
+    }
+
+    pub fn raise_alert(&self, subject: String, message: String) {
+        self.alerts.write().unwrap().insert(subject, message);
+    }
+
+    pub fn clear_alert(&self, subject: &str) {
+        self.alerts.write().unwrap().remove(subject);
+    }
+
+    pub fn alerts(&self) -> BTreeMap<String, String> {
+        self.alerts.read().unwrap().clone()
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.alerts.read().unwrap().is_empty()
+            && self
+                .components
+                .read()
+                .unwrap()
+                .values()
+                .all(|status| matches!(status, Status::Running | Status::Stopped))
+    }
+}
+
+/// Runs a component and restarts it with exponential backoff when it returns an error or panics.
+/// Returns once the component stops on its own, or with an error once it keeps failing.
+pub async fn supervise<F, Fut>(
+    component: &'static str,
+    health: Health,
+    mut start: F,
+) -> Result<(), AggregatorError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), AggregatorError>> + Send + 'static,
+{
+    let mut failures: Vec<Instant> = vec![];
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        health.set(component, Status::Running);
+        // the join set aborts the component if the supervisor itself is aborted
+        let mut task = JoinSet::new();
+        task.spawn(start());
+        let error = match task.join_next().await {
+            Some(Ok(Ok(()))) | None => {
+                tracing::info!("Component stopped. component:{}", component);
+                health.set(component, Status::Stopped);
+                return Ok(());
+            }
+            Some(Ok(Err(e))) => e.to_string(),
+            Some(Err(e)) if e.is_panic() => format!("panicked: {}", panic_message(e)),
+            Some(Err(e)) => e.to_string(),
+        };
+
+        let now = Instant::now();
+        failures.retain(|at| now.duration_since(*at) < FAILURE_WINDOW);
+        failures.push(now);
+        if failures.len() >= MAX_FAILURES {
+            tracing::error!(
+                "Component keeps failing, giving up. component:{} failures:{} window:{}s error:{}",
+                component,
+                failures.len(),
+                FAILURE_WINDOW.as_secs(),
+                error
+            );
+            health.set(component, Status::Failed(error.clone()));
+            return Err(AggregatorError::Custom(format!(
+                "{} failed {} times: {}",
+                component,
+                failures.len(),
+                error
+            )));
+        }
+
+        tracing::error!(
+            "Component failed, restarting. component:{} failures:{} backoff:{}s error:{}",
+            component,
+            failures.len(),
+            backoff.as_secs(),
+            error
+        );
+        health.set(
+            component,
+            Status::Restarting {
+                failures: failures.len(),
+                error,
+            },
+        );
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn panic_message(e: tokio::task::JoinError) -> String {
+    let panic = e.into_panic();
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::{supervise, Health, Status};
+    use crate::error::AggregatorError;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restarts_until_stopped() {
+        let health = Health::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&runs);
+        let result = supervise("flaky", health.clone(), move || {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 => Err(AggregatorError::Custom("rpc down".to_string())),
+                    1 => panic!("bad block"),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(health.components.read().unwrap()["flaky"], Status::Stopped);
+
+        let result = supervise("broken", health.clone(), || async {
+            Err(AggregatorError::Custom("rpc down".to_string()))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert!(!health.healthy());
+    }
+}
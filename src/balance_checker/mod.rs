@@ -1,26 +1,21 @@
-use std::{collections::HashMap, time::Duration};
+use std::time::Duration;
 
 use alloy_primitives::utils::parse_units;
-use tokio::time::sleep;
+use tokio::{sync::watch, time::sleep};
 
-use crate::chains::chains::{BalanceProvider, ChainProviders};
+use crate::chains::chains::{BalanceProvider, L1Set};
 use anyhow::Result;
 
 pub struct BalanceChecker {
-    pub providers: HashMap<String, ChainProviders>,
-    pub balance_threshold: HashMap<String, String>,
+    /// Follows config reloads, so added or changed L1s are checked too
+    pub l1s: watch::Receiver<L1Set>,
     pub time_threshold: u64,
 }
 
 impl BalanceChecker {
-    pub fn new(
-        providers: HashMap<String, ChainProviders>,
-        balance_threshold: HashMap<String, String>,
-        threshold: u64,
-    ) -> Self {
+    pub fn new(l1s: watch::Receiver<L1Set>, threshold: u64) -> Self {
         Self {
-            providers,
-            balance_threshold,
+            l1s,
             time_threshold: threshold,
         }
     }
@@ -30,9 +25,19 @@ impl BalanceChecker {
     pub async fn run(&self) -> Result<()> {
         tracing::info!("Balance checker running");
         loop {
-            for (chain, provider) in self.providers.clone() {
-                let threshold_balance =
-                    parse_units(self.balance_threshold.get(&chain).unwrap(), "ether")?;
+            let l1s = self.l1s.borrow().clone();
+            for (chain, provider) in l1s.providers {
+                let threshold_balance = match l1s
+                    .balance_thresholds
+                    .get(&chain)
+                    .map(|threshold| parse_units(threshold, "ether"))
+                {
+                    Some(Ok(threshold)) => threshold,
+                    _ => {
+                        tracing::warn!("No valid balance threshold. chain:{}", chain);
+                        continue;
+                    }
+                };
                 match provider
                     .balance_under_threshold(threshold_balance.get_absolute())
                     .await
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
-use alloy_primitives::U256;
-use anyhow::Result;
+use alloy_primitives::{FixedBytes, U256};
+use anyhow::{Context, Error, Result};
 
 use crate::{
     config::{L1Details, L2Details},
@@ -13,6 +13,7 @@ use super::{
     evm::{
         provider::{EVMProvider, EVMProviderConfig},
         sender::TwineChain::CommitBatchInfo,
+        tx_manager::TxManager,
     },
     solana::provider::SolanaProvider,
 };
@@ -35,17 +36,64 @@ pub trait FetchL2TransactionData {
     ) -> impl std::future::Future<Output = Result<CommitBatchInfo>> + Send;
 }
 
+/// A settlement transaction that has been included in an L1 block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LandedTx {
+    pub tx_hash: String,
+    pub block_number: u64,
+    pub block_hash: FixedBytes<32>,
+    pub gas_used: u64,
+    /// Price paid per unit of gas, in wei
+    pub effective_gas_price: u128,
+    /// Times the transaction was broadcast, fee bumped replacements included.
+    /// 0 when it was looked up by hash instead of sent.
+    pub broadcasts: u32,
+}
+
+impl LandedTx {
+    /// Fee paid for the transaction, in wei
+    pub fn fee(&self) -> u128 {
+        self.gas_used as u128 * self.effective_gas_price
+    }
+}
+
 pub trait L1Transactions {
+    /// Returns the landed transaction, or None if nothing was sent
     fn commit_batch(
         &self,
         params: CommitBatchInfo,
         height: u64,
-    ) -> impl std::future::Future<Output = Result<()>> + Send;
+    ) -> impl std::future::Future<Output = Result<Option<LandedTx>>> + Send;
 
+    /// Returns the landed transaction, or None if nothing was sent
     fn submit_proof(
         &self,
         params: PostParams,
-    ) -> impl std::future::Future<Output = Result<()>> + Send;
+    ) -> impl std::future::Future<Output = Result<Option<LandedTx>>> + Send;
+}
+
+/// Read only view of the settlement contract on an L1
+pub trait L1State {
+    fn last_committed_batch(&self) -> impl std::future::Future<Output = Result<u64>> + Send;
+
+    fn last_finalized_batch(&self) -> impl std::future::Future<Output = Result<u64>> + Send;
+
+    /// Last finalized batch as of `finalized_height`, a settlement no reorg can undo
+    fn settled_batch(&self) -> impl std::future::Future<Output = Result<u64>> + Send;
+
+    fn state_root(
+        &self,
+        batch: u64,
+    ) -> impl std::future::Future<Output = Result<FixedBytes<32>>> + Send;
+
+    /// Highest block that is final according to the configured confirmations or finality tag
+    fn finalized_height(&self) -> impl std::future::Future<Output = Result<u64>> + Send;
+
+    /// Where the transaction is currently included, or None if it is not on the canonical chain
+    fn tx_inclusion(
+        &self,
+        tx_hash: &str,
+    ) -> impl std::future::Future<Output = Result<Option<LandedTx>>> + Send;
 }
 
 #[derive(Clone)]
@@ -56,7 +104,7 @@ pub enum ChainProviders {
 }
 
 impl L1Transactions for ChainProviders {
-    async fn submit_proof(&self, params: PostParams) -> Result<()> {
+    async fn submit_proof(&self, params: PostParams) -> Result<Option<LandedTx>> {
         match self {
             ChainProviders::EVM(evmprovider) => evmprovider.submit_proof(params).await,
             ChainProviders::SVM(sp) => sp.submit_proof(params).await,
@@ -64,7 +112,7 @@ impl L1Transactions for ChainProviders {
         }
     }
 
-    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<()> {
+    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<Option<LandedTx>> {
         match self {
             ChainProviders::EVM(evmprovider) => evmprovider.commit_batch(params, height).await,
             ChainProviders::SVM(sp) => sp.commit_batch(params, height).await,
@@ -73,6 +121,56 @@ impl L1Transactions for ChainProviders {
     }
 }
 
+impl L1State for ChainProviders {
+    async fn last_committed_batch(&self) -> Result<u64> {
+        match self {
+            ChainProviders::EVM(evmprovider) => evmprovider.last_committed_batch().await,
+            ChainProviders::SVM(sp) => sp.last_committed_batch().await,
+            ChainProviders::DummyVM(dp) => dp.last_committed_batch().await,
+        }
+    }
+
+    async fn last_finalized_batch(&self) -> Result<u64> {
+        match self {
+            ChainProviders::EVM(evmprovider) => evmprovider.last_finalized_batch().await,
+            ChainProviders::SVM(sp) => sp.last_finalized_batch().await,
+            ChainProviders::DummyVM(dp) => dp.last_finalized_batch().await,
+        }
+    }
+
+    async fn settled_batch(&self) -> Result<u64> {
+        match self {
+            ChainProviders::EVM(evmprovider) => evmprovider.settled_batch().await,
+            ChainProviders::SVM(sp) => sp.settled_batch().await,
+            ChainProviders::DummyVM(dp) => dp.settled_batch().await,
+        }
+    }
+
+    async fn state_root(&self, batch: u64) -> Result<FixedBytes<32>> {
+        match self {
+            ChainProviders::EVM(evmprovider) => evmprovider.state_root(batch).await,
+            ChainProviders::SVM(sp) => sp.state_root(batch).await,
+            ChainProviders::DummyVM(dp) => dp.state_root(batch).await,
+        }
+    }
+
+    async fn finalized_height(&self) -> Result<u64> {
+        match self {
+            ChainProviders::EVM(evmprovider) => evmprovider.finalized_height().await,
+            ChainProviders::SVM(sp) => sp.finalized_height().await,
+            ChainProviders::DummyVM(dp) => dp.finalized_height().await,
+        }
+    }
+
+    async fn tx_inclusion(&self, tx_hash: &str) -> Result<Option<LandedTx>> {
+        match self {
+            ChainProviders::EVM(evmprovider) => evmprovider.tx_inclusion(tx_hash).await,
+            ChainProviders::SVM(sp) => sp.tx_inclusion(tx_hash).await,
+            ChainProviders::DummyVM(dp) => dp.tx_inclusion(tx_hash).await,
+        }
+    }
+}
+
 impl FetchL2TransactionData for ChainProviders {
     async fn fetch_commit_batch(&self, height: u64) -> Result<CommitBatchInfo> {
         match self {
@@ -112,39 +210,47 @@ impl BalanceProvider for ChainProviders {
 }
 
 /// Makes chain providers from data in the config
-pub fn make_providers(l1s: HashMap<String, L1Details>) -> HashMap<String, ChainProviders> {
-    l1s.iter()
-        .map(|(key, detail)| {
-            let provider = match detail {
-                L1Details::Solana(_solana_config) => todo!(),
-                L1Details::EVM(evmconfig) => {
-                    let evm_config = EVMProviderConfig::new(
-                        evmconfig.rpc.clone(),
-                        evmconfig.private_key.clone(),
-                        evmconfig.contract.clone(),
-                    );
-
-                    evm_config
-                        .build()
-                        .map(|provider| {
-                            ChainProviders::EVM(EVMProvider {
-                                provider,
-                                config: evm_config,
-                            })
-                        })
-                        .unwrap_or_else(|_| panic!("Failed building provider!"))
-                }
-            };
-            (key.to_string(), provider)
-        })
-        .collect()
+pub async fn make_providers(
+    l1s: HashMap<String, L1Details>,
+) -> Result<HashMap<String, ChainProviders>> {
+    let mut providers = HashMap::new();
+    for (chain, detail) in l1s.iter() {
+        let provider = make_provider(detail)
+            .await
+            .with_context(|| format!("Failed building provider for {}", chain))?;
+        providers.insert(chain.to_string(), provider);
+    }
+    Ok(providers)
+}
+
+/// Makes the provider of a single chain
+pub async fn make_provider(detail: &L1Details) -> Result<ChainProviders> {
+    match detail {
+        L1Details::Solana(_solana_config) => Err(Error::msg("solana not supported")),
+        L1Details::EVM(evmconfig) => {
+            let evm_config = EVMProviderConfig::from_evm_config(evmconfig);
+            let rpcs = evm_config.build().await?;
+
+            Ok(ChainProviders::EVM(EVMProvider {
+                rpcs,
+                tx_manager: TxManager::new(evm_config.gas.clone())?,
+                config: evm_config,
+            }))
+        }
+    }
+}
+
+/// The L1s currently settled to, with their balance thresholds. Replaced as a whole when the config is reloaded.
+#[derive(Clone, Default)]
+pub struct L1Set {
+    pub providers: HashMap<String, ChainProviders>,
+    pub balance_thresholds: HashMap<String, String>,
 }
 
-pub fn make_l2_provider(l2: L2Details) -> EVMProvider {
-    // We won't use this key for anythng
-    let dummy_private_key = "2a871d0798f97d79848a013d4936a73bf4cc922c825d33c1cf7073dff6d409c6";
-    let evm_cfg =
-        EVMProviderConfig::new(l2.rpc, dummy_private_key.to_string(), l2.messenger_contract);
+pub async fn make_l2_provider(l2: L2Details) -> EVMProvider {
+    // the L2 provider only reads, so it has no signer
+    let evm_cfg = EVMProviderConfig::new(l2.rpc, l2.messenger_contract)
+        .with_fallbacks(l2.fallback_rpcs, l2.read_quorum);
 
-    EVMProvider::new(evm_cfg)
+    EVMProvider::new(evm_cfg).await
 }
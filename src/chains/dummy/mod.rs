@@ -1,39 +1,144 @@
-use crate::chains::chains::{BalanceProvider, L1Transactions};
-use alloy_primitives::U256;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    chains::chains::{BalanceProvider, L1State, L1Transactions, LandedTx},
+    error::AggregatorError,
+};
+use alloy_primitives::{FixedBytes, U256};
 use anyhow::Result;
 
 use super::{chains::FetchL2TransactionData, evm::sender::TwineChain::CommitBatchInfo};
 
-#[derive(Clone)]
-pub struct DummyProvider {}
+#[derive(Default)]
+struct DummyChain {
+    last_committed: u64,
+    last_finalized: u64,
+    block_number: u64,
+    txs: HashMap<String, LandedTx>,
+    /// Batches whose proof the contract rejects
+    rejected: HashSet<u64>,
+}
+
+impl DummyChain {
+    /// Every transaction lands in a block of its own
+    fn land(&mut self, kind: &str, batch: u64) -> LandedTx {
+        self.block_number += 1;
+        let tx = LandedTx {
+            tx_hash: format!("{}-{}", kind, batch),
+            block_number: self.block_number,
+            block_hash: FixedBytes::from(U256::from(self.block_number)),
+            gas_used: 0,
+            effective_gas_price: 0,
+            broadcasts: 1,
+        };
+        self.txs.insert(tx.tx_hash.clone(), tx.clone());
+        tx
+    }
+}
+
+/// In-memory chain for tests. Batch numbers follow L2 heights, state roots are zero,
+/// and every block is final as soon as it is produced.
+#[derive(Clone, Default)]
+pub struct DummyProvider {
+    chain: Arc<Mutex<DummyChain>>,
+}
+
+impl DummyProvider {
+    /// Makes the contract reject the proof of the batch, as it would an invalid one
+    pub fn reject_proof(&self, batch: u64) {
+        self.chain.lock().unwrap().rejected.insert(batch);
+    }
+}
 
 impl BalanceProvider for DummyProvider {
     async fn query_balance(&self) -> Result<U256> {
-        todo!()
+        Ok(U256::MAX)
     }
 
     async fn balance_under_threshold(&self, threshold: U256) -> Result<(bool, String)> {
-        let _ = threshold;
-        todo!()
+        let balance = self.query_balance().await?;
+        Ok((balance < threshold, format!("{}wei", balance)))
     }
 }
 
 impl L1Transactions for DummyProvider {
-    async fn submit_proof(&self, params: crate::types::PostParams) -> Result<()> {
-        let _ = params;
-        todo!()
+    async fn submit_proof(&self, params: crate::types::PostParams) -> Result<Option<LandedTx>> {
+        let batch = params.height();
+        let mut chain = self.chain.lock().unwrap();
+        if batch <= chain.last_finalized {
+            return Err(AggregatorError::BatchAlreadyFinalized(batch).into());
+        }
+        if batch > chain.last_committed {
+            return Err(AggregatorError::BatchNotCommitted(batch).into());
+        }
+        if chain.rejected.contains(&batch) {
+            return Err(AggregatorError::InvalidProof(batch).into());
+        }
+        chain.last_finalized = batch;
+        Ok(Some(chain.land("finalize", batch)))
     }
 
-    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<()> {
-        let _ = params;
+    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<Option<LandedTx>> {
         let _ = height;
-        todo!()
+        let batch = params.batchNumber;
+        let mut chain = self.chain.lock().unwrap();
+        if batch <= chain.last_committed {
+            return Err(AggregatorError::BatchAlreadyCommitted(batch).into());
+        }
+        if batch != chain.last_committed + 1 {
+            return Err(AggregatorError::WrongBatchNumber {
+                expected: chain.last_committed + 1,
+                provided: batch,
+            }
+            .into());
+        }
+        chain.last_committed = batch;
+        Ok(Some(chain.land("commit", batch)))
+    }
+}
+
+impl L1State for DummyProvider {
+    async fn last_committed_batch(&self) -> Result<u64> {
+        Ok(self.chain.lock().unwrap().last_committed)
+    }
+
+    async fn last_finalized_batch(&self) -> Result<u64> {
+        Ok(self.chain.lock().unwrap().last_finalized)
+    }
+
+    async fn settled_batch(&self) -> Result<u64> {
+        self.last_finalized_batch().await
+    }
+
+    async fn state_root(&self, batch: u64) -> Result<FixedBytes<32>> {
+        let _ = batch;
+        Ok(FixedBytes::ZERO)
+    }
+
+    async fn finalized_height(&self) -> Result<u64> {
+        Ok(self.chain.lock().unwrap().block_number)
+    }
+
+    async fn tx_inclusion(&self, tx_hash: &str) -> Result<Option<LandedTx>> {
+        Ok(self.chain.lock().unwrap().txs.get(tx_hash).cloned())
     }
 }
 
 impl FetchL2TransactionData for DummyProvider {
     async fn fetch_commit_batch(&self, height: u64) -> Result<CommitBatchInfo> {
-        let _ = height;
-        todo!()
+        Ok(CommitBatchInfo {
+            batchNumber: height,
+            batchHash: FixedBytes::ZERO,
+            previousStateRoot: FixedBytes::ZERO,
+            stateRoot: FixedBytes::ZERO,
+            transactionRoot: FixedBytes::ZERO,
+            receiptRoot: FixedBytes::ZERO,
+            depositTransactionObject: vec![],
+            forcedTransactionObjects: vec![],
+            otherTransactions: vec![],
+        })
     }
 }
@@ -0,0 +1,228 @@
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{Error, Result};
+use futures::future::join_all;
+
+use crate::error::AggregatorError;
+
+use super::provider::AlloyProvider;
+
+static MAX_SCORE: i64 = 10;
+static MIN_SCORE: i64 = -10;
+
+/// Score lost on a failure. Larger than the gain on success, so a flaky node drops down quickly.
+static FAILURE_PENALTY: i64 = 3;
+
+pub struct Endpoint {
+    pub url: String,
+    pub provider: AlloyProvider,
+    score: AtomicI64,
+}
+
+/// RPC endpoints of one chain. Requests go to the healthiest endpoint first and fail over to the others.
+/// Reads that the poster relies on can require `read_quorum` endpoints to return the same value.
+#[derive(Clone)]
+pub struct RpcPool {
+    endpoints: Arc<Vec<Endpoint>>,
+    pub read_quorum: usize,
+}
+
+impl RpcPool {
+    pub fn new(endpoints: Vec<(String, AlloyProvider)>, read_quorum: usize) -> Self {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|(url, provider)| Endpoint {
+                url,
+                provider,
+                score: AtomicI64::new(0),
+            })
+            .collect();
+
+        Self {
+            endpoints: Arc::new(endpoints),
+            read_quorum,
+        }
+    }
+
+    /// Provider of the healthiest endpoint
+    pub fn primary(&self) -> &AlloyProvider {
+        &self.endpoints[self.ranked()[0]].provider
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Url and health score of every endpoint
+    pub fn health(&self) -> Vec<(String, i64)> {
+        self.endpoints
+            .iter()
+            .map(|e| (e.url.clone(), e.score.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Runs the request on the healthiest endpoint, falling over to the next one on failure
+    pub async fn failover<T, E, F, Fut>(&self, request: F) -> Result<T>
+    where
+        E: Display + Into<Error>,
+        F: Fn(AlloyProvider) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut last_error = None;
+        for idx in self.ranked() {
+            match request(self.endpoints[idx].provider.clone()).await {
+                Ok(value) => {
+                    self.record(idx, true);
+                    return Ok(value);
+                }
+                Err(e) => {
+                    self.record(idx, false);
+                    tracing::warn!(
+                        "RPC request failed. url:{} error:{}",
+                        self.endpoints[idx].url,
+                        e
+                    );
+                    last_error = Some(e.into());
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::msg("No RPC endpoints configured")))
+    }
+
+    /// Runs the request on every endpoint and returns the value that at least `read_quorum` of them agree on.
+    /// Endpoints that fail or disagree with the quorum lose health score.
+    pub async fn quorum_read<T, E, F, Fut>(&self, request: F) -> Result<T>
+    where
+        T: PartialEq + Debug,
+        E: Display + Into<Error>,
+        F: Fn(AlloyProvider) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if self.read_quorum <= 1 {
+            return self.failover(request).await;
+        }
+
+        let answers = self.read_all(request).await;
+        if answers.len() > 1 {
+            tracing::warn!("RPC endpoints disagree. answers: {:?}", answers);
+        }
+        self.pick(answers)
+    }
+
+    /// Like `quorum_read`, but every endpoint is asked even with a quorum of one, and any
+    /// disagreement between endpoints is an error, whatever the majority. Used for data that ends
+    /// up on the L1s, so a disputed answer is never used.
+    pub async fn cross_check<T, E, F, Fut>(&self, request: F) -> Result<T>
+    where
+        T: PartialEq + Debug,
+        E: Display + Into<Error>,
+        F: Fn(AlloyProvider) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let answers = self.read_all(request).await;
+        if answers.len() > 1 {
+            let urls: Vec<Vec<&str>> = answers
+                .iter()
+                .map(|(_, endpoints)| {
+                    endpoints
+                        .iter()
+                        .map(|idx| self.endpoints[*idx].url.as_str())
+                        .collect()
+                })
+                .collect();
+            tracing::error!(
+                "ALERT: RPC endpoints returned different data. endpoints:{:?} answers:{:?}",
+                urls,
+                answers
+            );
+            return Err(AggregatorError::Custom(format!(
+                "RPC endpoints disagree, refusing disputed data. endpoints:{:?}",
+                urls
+            ))
+            .into());
+        }
+        self.pick(answers)
+    }
+
+    /// Distinct answers of all endpoints with the endpoints that returned them.
+    /// Failed endpoints lose health score.
+    async fn read_all<T, E, F, Fut>(&self, request: F) -> Vec<(T, Vec<usize>)>
+    where
+        T: PartialEq,
+        E: Display,
+        F: Fn(AlloyProvider) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let results = join_all(self.endpoints.iter().map(|e| request(e.provider.clone()))).await;
+
+        let mut answers: Vec<(T, Vec<usize>)> = vec![];
+        for (idx, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(value) => match answers.iter_mut().find(|(v, _)| *v == value) {
+                    Some((_, endpoints)) => endpoints.push(idx),
+                    None => answers.push((value, vec![idx])),
+                },
+                Err(e) => {
+                    self.record(idx, false);
+                    tracing::warn!(
+                        "RPC request failed. url:{} error:{}",
+                        self.endpoints[idx].url,
+                        e
+                    );
+                }
+            }
+        }
+        answers
+    }
+
+    /// The answer returned by the most endpoints, if at least `read_quorum` of them agree.
+    /// Endpoints that disagree with it lose health score.
+    fn pick<T: Debug>(&self, mut answers: Vec<(T, Vec<usize>)>) -> Result<T> {
+        let Some(winner) = answers
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, (_, endpoints))| (endpoints.len(), std::cmp::Reverse(*i)))
+            .map(|(i, _)| i)
+            .filter(|i| answers[*i].1.len() >= self.read_quorum.max(1))
+        else {
+            return Err(AggregatorError::Custom(format!(
+                "No read quorum of {} among {} endpoints. answers: {:?}",
+                self.read_quorum,
+                self.endpoints.len(),
+                answers
+            ))
+            .into());
+        };
+
+        for (i, (_, endpoints)) in answers.iter().enumerate() {
+            for idx in endpoints {
+                self.record(*idx, i == winner);
+            }
+        }
+        Ok(answers.swap_remove(winner).0)
+    }
+
+    /// Endpoint indexes, healthiest first. Ties keep the configured order.
+    fn ranked(&self) -> Vec<usize> {
+        let mut indexes: Vec<usize> = (0..self.endpoints.len()).collect();
+        indexes.sort_by_key(|idx| -self.endpoints[*idx].score.load(Ordering::Relaxed));
+        indexes
+    }
+
+    fn record(&self, idx: usize, success: bool) {
+        let change = if success { 1 } else { -FAILURE_PENALTY };
+        let _ =
+            self.endpoints[idx]
+                .score
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |score| {
+                    Some((score + change).clamp(MIN_SCORE, MAX_SCORE))
+                });
+    }
+}
@@ -1,3 +1,6 @@
+pub mod endpoints;
 pub mod provider;
 pub mod query;
 pub mod sender;
+pub mod signer;
+pub mod tx_manager;
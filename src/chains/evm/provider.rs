@@ -1,26 +1,34 @@
 use std::str::FromStr;
 
-use alloy::{
-    network::EthereumWallet,
-    signers::local::PrivateKeySigner,
-    transports::http::{Client, Http},
-};
+use alloy::{network::EthereumWallet, signers::local::PrivateKeySigner, transports::BoxTransport};
 use alloy_primitives::Address;
 use alloy_provider::WalletProvider;
 use alloy_provider::{fillers::FillProvider, ProviderBuilder, RootProvider};
 use anyhow::{Context, Error};
 
+use crate::config::{EVMConfig, FinalityConfig, GasConfig, SignerConfig};
+
+use super::{endpoints::RpcPool, signer::make_wallet, tx_manager::TxManager};
+
 #[derive(Debug, Clone)]
 pub struct EVMProviderConfig {
     pub rpc_url: String,
-    pub private_key: String,
+    /// Used when `rpc_url` fails, and for quorum reads
+    pub fallback_rpc_urls: Vec<String>,
+    /// Number of endpoints that must agree on a read
+    pub read_quorum: usize,
+    /// None for read-only providers such as the L2 one
+    pub signer: Option<SignerConfig>,
     pub contract_address: Address,
+    pub gas: GasConfig,
+    pub finality: FinalityConfig,
 }
 
 #[derive(Clone)]
 pub struct EVMProvider {
     pub config: EVMProviderConfig,
-    pub provider: AlloyProvider,
+    pub rpcs: RpcPool,
+    pub tx_manager: TxManager,
 }
 
 pub type AlloyProvider = FillProvider<
@@ -40,54 +48,96 @@ pub type AlloyProvider = FillProvider<
         >,
         alloy_provider::fillers::WalletFiller<EthereumWallet>,
     >,
-    RootProvider<Http<Client>>,
-    Http<Client>,
+    RootProvider<BoxTransport>,
+    BoxTransport,
     alloy::network::Ethereum,
 >;
 
 impl EVMProviderConfig {
-    pub fn new(rpc_url: String, private_key: String, contract_address: String) -> Self {
+    pub fn new(rpc_url: String, contract_address: String) -> Self {
         let contract = Address::from_str(contract_address.trim_start_matches("0x"))
             .expect("Invalid contract address");
 
         Self {
             rpc_url,
-            private_key,
+            fallback_rpc_urls: vec![],
+            read_quorum: 1,
+            signer: None,
             contract_address: contract,
+            gas: GasConfig::default(),
+            finality: FinalityConfig::default(),
         }
     }
+
+    /// Provider config for an L1 from its config file entry
+    pub fn from_evm_config(evmconfig: &EVMConfig) -> Self {
+        Self::new(evmconfig.rpc.clone(), evmconfig.contract.clone())
+            .with_signer(evmconfig.signer.clone())
+            .with_gas(evmconfig.gas.clone())
+            .with_finality(evmconfig.finality.clone())
+            .with_fallbacks(evmconfig.fallback_rpcs.clone(), evmconfig.read_quorum)
+    }
+
+    pub fn with_signer(mut self, signer: SignerConfig) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn with_gas(mut self, gas: GasConfig) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    pub fn with_finality(mut self, finality: FinalityConfig) -> Self {
+        self.finality = finality;
+        self
+    }
+
+    pub fn with_fallbacks(mut self, fallback_rpc_urls: Vec<String>, read_quorum: usize) -> Self {
+        self.fallback_rpc_urls = fallback_rpc_urls;
+        self.read_quorum = read_quorum;
+        self
+    }
 }
 
 impl EVMProvider {
-    pub fn new(config: EVMProviderConfig) -> Self {
-        let provider = config.build().expect("Failed to build EVM Provider");
-        Self { config, provider }
+    pub async fn new(config: EVMProviderConfig) -> Self {
+        let rpcs = config.build().await.expect("Failed to build EVM Provider");
+        let tx_manager = TxManager::new(config.gas.clone()).expect("Invalid gas config");
+        Self {
+            config,
+            rpcs,
+            tx_manager,
+        }
     }
 
     pub fn address(&self) -> String {
-        let addr = self.provider.default_signer_address();
+        let addr = self.rpcs.primary().default_signer_address();
         addr.to_string()
     }
 }
 
 impl EVMProviderConfig {
-    pub fn build(&self) -> Result<AlloyProvider, Error> {
-        let signer: PrivateKeySigner = self
-            .private_key
-            .trim_start_matches("0x")
-            .parse()
-            .with_context(|| "Error parsing private key")?;
-        let wallet = EthereumWallet::from(signer);
-
-        let provider = ProviderBuilder::new()
-            .with_recommended_fillers()
-            .wallet(wallet)
-            .on_http(
-                self.rpc_url
-                    .parse()
-                    .with_context(|| "Error parsing RPC URL")?,
-            );
-
-        Ok(provider)
+    /// Builds a provider for the main rpc url and each fallback.
+    /// The transport follows the url: http(s), ws(s), or an ipc socket path.
+    pub async fn build(&self) -> Result<RpcPool, Error> {
+        let wallet = match &self.signer {
+            Some(signer) => make_wallet(signer)?,
+            // never used to sign, read-only providers still need a wallet filler
+            None => EthereumWallet::from(PrivateKeySigner::random()),
+        };
+
+        let mut endpoints = vec![];
+        for url in std::iter::once(&self.rpc_url).chain(&self.fallback_rpc_urls) {
+            let provider = ProviderBuilder::new()
+                .with_recommended_fillers()
+                .wallet(wallet.clone())
+                .on_builtin(url)
+                .await
+                .with_context(|| format!("Error connecting to RPC {}", url))?;
+            endpoints.push((url.clone(), provider));
+        }
+
+        Ok(RpcPool::new(endpoints, self.read_quorum))
     }
 }
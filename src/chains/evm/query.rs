@@ -1,30 +1,38 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use crate::{
-    chains::chains::{BalanceProvider, FetchL2TransactionData},
+    chains::chains::{BalanceProvider, FetchL2TransactionData, L1State, LandedTx},
     error::AggregatorError,
     MAX_RETRIES,
 };
 
 use super::{
-    provider::EVMProvider,
+    provider::{AlloyProvider, EVMProvider},
     sender::TwineChain::{self, CommitBatchInfo},
 };
 use alloy::{
-    eips::BlockNumberOrTag,
+    eips::{BlockId, BlockNumberOrTag},
     rpc::types::{Block, Filter},
     sol,
     sol_types::SolEvent,
 };
-use alloy_primitives::{utils::format_units, FixedBytes, U256};
+use alloy_primitives::{utils::format_units, Address, FixedBytes, TxHash, U256};
 use alloy_provider::{Provider, WalletProvider};
 use anyhow::Result;
+use futures::StreamExt;
+use tokio::time::{interval, timeout};
+
+/// Seconds between L2 head polls on endpoints without subscriptions
+static L2_POLL_INTERVAL: u64 = 2;
+/// Seconds to wait for the L2 to reach a height before fetching it anyway
+static L2_HEAD_TIMEOUT: u64 = 60;
 
 impl BalanceProvider for EVMProvider {
     async fn query_balance(&self) -> Result<U256> {
-        let poster = self.provider.default_signer_address();
-        let balance = self.provider.get_balance(poster).await?;
-        Ok(balance)
+        let poster = self.rpcs.primary().default_signer_address();
+        self.rpcs
+            .failover(|provider| async move { provider.get_balance(poster).await })
+            .await
     }
 
     async fn balance_under_threshold(&self, threshold: U256) -> Result<(bool, String)> {
@@ -34,6 +42,99 @@ impl BalanceProvider for EVMProvider {
     }
 }
 
+impl L1State for EVMProvider {
+    async fn last_committed_batch(&self) -> Result<u64> {
+        let address = self.config.contract_address;
+        self.rpcs
+            .quorum_read(|provider| async move {
+                let contract = TwineChain::new(address, provider);
+                contract.lastCommittedBatch().call().await.map(|r| r._0)
+            })
+            .await
+    }
+
+    async fn last_finalized_batch(&self) -> Result<u64> {
+        let address = self.config.contract_address;
+        self.rpcs
+            .quorum_read(|provider| async move {
+                let contract = TwineChain::new(address, provider);
+                contract.lastFinalizedBatch().call().await.map(|r| r._0)
+            })
+            .await
+    }
+
+    async fn settled_batch(&self) -> Result<u64> {
+        let address = self.config.contract_address;
+        let height = self.finalized_height().await?;
+        self.rpcs
+            .quorum_read(|provider| async move {
+                let contract = TwineChain::new(address, provider);
+                contract
+                    .lastFinalizedBatch()
+                    .block(BlockId::number(height))
+                    .call()
+                    .await
+                    .map(|r| r._0)
+            })
+            .await
+    }
+
+    async fn state_root(&self, batch: u64) -> Result<FixedBytes<32>> {
+        let address = self.config.contract_address;
+        self.rpcs
+            .quorum_read(|provider| async move {
+                let contract = TwineChain::new(address, provider);
+                contract.stateRoots(batch).call().await.map(|r| r._0)
+            })
+            .await
+    }
+
+    async fn finalized_height(&self) -> Result<u64> {
+        let tag = match self.config.finality.tag.as_deref() {
+            Some("safe") => BlockNumberOrTag::Safe,
+            Some("finalized") => BlockNumberOrTag::Finalized,
+            _ => {
+                let latest = self
+                    .rpcs
+                    .quorum_read(|provider| async move { provider.get_block_number().await })
+                    .await?;
+                return Ok(latest.saturating_sub(self.config.finality.confirmations));
+            }
+        };
+
+        let block = self
+            .rpcs
+            .quorum_read(|provider| async move {
+                provider
+                    .get_block_by_number(tag, false)
+                    .await
+                    .map(|block| block.map(|b| b.header.number))
+            })
+            .await?
+            .ok_or_else(|| AggregatorError::Custom(format!("No {} block", tag)))?;
+        Ok(block)
+    }
+
+    async fn tx_inclusion(&self, tx_hash: &str) -> Result<Option<LandedTx>> {
+        let hash: TxHash = tx_hash.parse()?;
+        let receipt = self
+            .rpcs
+            .quorum_read(|provider| async move { provider.get_transaction_receipt(hash).await })
+            .await?;
+
+        Ok(receipt.and_then(|receipt| {
+            Some(LandedTx {
+                tx_hash: tx_hash.to_string(),
+                block_number: receipt.block_number?,
+                block_hash: receipt.block_hash?,
+                gas_used: receipt.gas_used as u64,
+                effective_gas_price: receipt.effective_gas_price,
+                broadcasts: 0,
+            })
+        }))
+    }
+}
+
 sol! {
     #[sol(rpc)]
     contract L2Messenger {
@@ -58,49 +159,26 @@ sol! {
 }
 
 impl FetchL2TransactionData for EVMProvider {
+    /// Builds the batch from every configured L2 endpoint and only returns it if they agree on it,
+    /// so a single faulty or compromised node cannot change what is committed on the L1s.
     async fn fetch_commit_batch(&self, height: u64) -> Result<CommitBatchInfo> {
+        let messenger = self.config.contract_address;
         let mut attempt = 0;
 
+        // a proof can arrive before our L2 nodes have the block
+        if let Err(e) = self.wait_for_height(height).await {
+            tracing::warn!("L2 head not reached. height:{} error:{}", height, e);
+        }
+
         loop {
             match self
-                .provider
-                .get_block_by_number(BlockNumberOrTag::Number(height), true)
+                .rpcs
+                .cross_check(|provider| async move {
+                    EVMProvider::build_commit_batch(&provider, messenger, height).await
+                })
                 .await
             {
-                Ok(block) => {
-                    if let Some(blk) = block {
-                        let prev_state_root = match self
-                            .provider
-                            .get_block_by_number(
-                                BlockNumberOrTag::Number(blk.header.number - 1),
-                                true,
-                            )
-                            .await
-                        {
-                            Ok(xx) => xx.unwrap().header.state_root,
-                            Err(_) => {
-                                attempt += 1;
-                                continue;
-                            }
-                        };
-                        match self.filter_l2_transactions(blk.header.hash).await {
-                            Ok(filter) => {
-                                return Ok(EVMProvider::generate_commit_params(
-                                    blk,
-                                    filter,
-                                    prev_state_root,
-                                ))
-                            }
-                            Err(e) => {
-                                attempt += 1;
-                                tracing::warn!("Failed to get logs: {}", e.to_string());
-                            }
-                        }
-                    } else {
-                        attempt += 1;
-                        tracing::warn!("No result in block");
-                    }
-                }
+                Ok(batch) => return Ok(batch),
                 Err(e) => {
                     attempt += 1;
                     if attempt > MAX_RETRIES {
@@ -110,7 +188,11 @@ impl FetchL2TransactionData for EVMProvider {
                         )
                         .into());
                     }
-                    tracing::warn!("Failed to query block: {}", e.to_string());
+                    tracing::warn!(
+                        "Failed to fetch commit batch. height:{} error:{}",
+                        height,
+                        e
+                    );
                 }
             }
         }
@@ -118,8 +200,77 @@ impl FetchL2TransactionData for EVMProvider {
 }
 
 impl EVMProvider {
+    /// Waits until the L2 head reaches `height`. New heads arrive through a subscription
+    /// on websocket and ipc endpoints. Http endpoints, which cannot subscribe, are polled.
+    pub async fn wait_for_height(&self, height: u64) -> Result<()> {
+        let provider = self.rpcs.primary().clone();
+        if provider.get_block_number().await? >= height {
+            return Ok(());
+        }
+
+        let wait = async {
+            match provider.subscribe_blocks().await {
+                Ok(subscription) => {
+                    let mut heads = subscription.into_stream();
+                    while let Some(head) = heads.next().await {
+                        tracing::debug!("New L2 head. height:{}", head.header.number);
+                        if head.header.number >= height {
+                            return Ok(());
+                        }
+                    }
+                    Err(AggregatorError::Custom("L2 head subscription closed".to_string()).into())
+                }
+                Err(_) => {
+                    let mut poll = interval(Duration::from_secs(L2_POLL_INTERVAL));
+                    loop {
+                        poll.tick().await;
+                        if provider.get_block_number().await? >= height {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        };
+
+        timeout(Duration::from_secs(L2_HEAD_TIMEOUT), wait)
+            .await
+            .map_err(|_| {
+                AggregatorError::Custom(format!("Timed out waiting for L2 height {}", height))
+            })?
+    }
+
+    /// Commit batch for an L2 height as seen by a single endpoint
+    async fn build_commit_batch(
+        provider: &AlloyProvider,
+        messenger: Address,
+        height: u64,
+    ) -> Result<CommitBatchInfo> {
+        let block = provider
+            .get_block_by_number(BlockNumberOrTag::Number(height), true)
+            .await?
+            .ok_or_else(|| AggregatorError::Custom(format!("No block at height {}", height)))?;
+
+        let prev_state_root = provider
+            .get_block_by_number(BlockNumberOrTag::Number(block.header.number - 1), false)
+            .await?
+            .ok_or_else(|| {
+                AggregatorError::Custom(format!("No block at height {}", block.header.number - 1))
+            })?
+            .header
+            .state_root;
+
+        let filter =
+            EVMProvider::filter_l2_transactions(provider, messenger, block.header.hash).await?;
+        Ok(EVMProvider::generate_commit_params(
+            block,
+            filter,
+            prev_state_root,
+        ))
+    }
+
     pub async fn filter_l2_transactions(
-        &self,
+        provider: &AlloyProvider,
+        messenger: Address,
         block_hash: FixedBytes<32>,
     ) -> Result<HashMap<u64, L2TxType>> {
         let mut tx_types = HashMap::<u64, L2TxType>::new();
@@ -127,9 +278,9 @@ impl EVMProvider {
         let filter = Filter::new()
             .events(["L1TokenDeposit()", "ForcedWithdrawal(address,address,address,address,uint256,uint256,uint256,uint256,bytes)"])
             .at_block_hash(block_hash)
-            .address(self.config.contract_address);
+            .address(messenger);
 
-        let logs = self.provider.get_logs(&filter).await?;
+        let logs = provider.get_logs(&filter).await?;
 
         for l in logs {
             match l.topic0() {
@@ -1,23 +1,29 @@
-use std::time::Duration;
-
-use crate::{chains::chains::L1Transactions, types::PostParams};
+use crate::{
+    chains::chains::{L1Transactions, LandedTx},
+    error::AggregatorError,
+    types::PostParams,
+};
 
 use super::provider::EVMProvider;
-use alloy::{hex::ToHexExt, rpc::types::TransactionRequest, sol, sol_types::SolValue};
+use alloy::{
+    hex::ToHexExt,
+    network::TransactionBuilder,
+    rpc::types::TransactionRequest,
+    sol,
+    sol_types::{decode_revert_reason, SolInterface, SolValue},
+    transports::RpcError,
+};
 
 use alloy_primitives::U256;
-use alloy_provider::Provider;
+use alloy_provider::{Provider, WalletProvider};
 use anyhow::Result;
-use tokio::time::sleep;
-use TwineChain::CommitBatchInfo;
-
-static MAX_RETRIES: i32 = 10;
+use TwineChain::{CommitBatchInfo, TwineChainErrors};
 
 sol! {
     #[sol(rpc)]
     contract TwineChain {
 
-        #[derive(Debug)]
+        #[derive(Debug, PartialEq)]
         struct TransactionObject {
             uint256 chainId;
             uint256 nonce;
@@ -33,14 +39,14 @@ sol! {
             bytes32 s;
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, PartialEq)]
         struct AccessList {
             address _address;
             bytes32[] storageKeys;
         }
 
 
-        #[derive(Debug)]
+        #[derive(Debug, PartialEq)]
         struct CommitBatchInfo{
             uint64 batchNumber;
             bytes32 batchHash;
@@ -58,27 +64,51 @@ sol! {
 
         #[derive(Debug)]
         function finalizeBatch(uint256 batchNumber, bytes calldata _proofBytes) external;
+
+        #[derive(Debug)]
+        error InvalidProof();
+
+        #[derive(Debug)]
+        error InvalidBatchNumber(uint64 expected, uint64 provided);
+
+        #[derive(Debug)]
+        error BatchAlreadyCommitted(uint64 batchNumber);
+
+        #[derive(Debug)]
+        error BatchAlreadyFinalized(uint64 batchNumber);
+
+        #[derive(Debug)]
+        error BatchNotCommitted(uint64 batchNumber);
+
+        #[derive(Debug)]
+        function lastCommittedBatch() external view returns (uint64);
+
+        #[derive(Debug)]
+        function lastFinalizedBatch() external view returns (uint64);
+
+        #[derive(Debug)]
+        function stateRoots(uint64 batchNumber) external view returns (bytes32);
     }
 }
 
 impl L1Transactions for EVMProvider {
-    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<()> {
+    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<Option<LandedTx>> {
         tracing::info!("Commit batch for batch: {}", params.batchNumber);
         let batch = params.batchNumber;
-        let contract = TwineChain::new(self.config.contract_address, self.provider.clone());
+        let contract = TwineChain::new(self.config.contract_address, self.rpcs.primary().clone());
         let abi_encoded_params = params.abi_encode_packed().as_slice().encode_hex();
         tracing::debug!("Abi Encoded Commit Batch Params: {}", abi_encoded_params);
 
         let tx_data = contract.commitBatch(params);
 
-        let tx_req = tx_data
-            // .max_fee_per_gas(20000000000)
-            // .max_priority_fee_per_gas(2000000)
-            .into_transaction_request();
+        let tx_req = self
+            .simulate(tx_data.into_transaction_request(), batch)
+            .await?;
 
         match self.send_transaction(tx_req).await {
-            Ok(_) => {
+            Ok(landed) => {
                 tracing::info!("Commited batch for height: {} batch: {}", height, batch);
+                Ok(Some(landed))
             }
             Err(e) => {
                 tracing::error!(
@@ -87,18 +117,18 @@ impl L1Transactions for EVMProvider {
                     batch,
                     e.to_string()
                 );
-                return Err(e);
+                Err(e)
             }
         }
-        Ok(())
     }
 
-    async fn submit_proof(&self, params: PostParams) -> Result<()> {
+    async fn submit_proof(&self, params: PostParams) -> Result<Option<LandedTx>> {
         tracing::info!("Submitting proof for batch: {}", params.height());
         match params {
             PostParams::RiscZero(_evm_risc0_params, _block) => todo!(),
             PostParams::Sp1(sp1_params, block) => {
-                let contract = TwineChain::new(self.config.contract_address, self.provider.clone());
+                let contract =
+                    TwineChain::new(self.config.contract_address, self.rpcs.primary().clone());
 
                 let plonk_proof = sp1_params.plonk_proof;
                 let block = U256::from(block);
@@ -107,15 +137,14 @@ impl L1Transactions for EVMProvider {
 
                 let tx_data = contract.finalizeBatch(block, plonk_proof.clone());
 
-                let tx_req = tx_data
-                    // .max_fee_per_gas(20000000000)
-                    // .max_priority_fee_per_gas(2000000)
-                    .into_transaction_request();
+                let tx_req = self
+                    .simulate(tx_data.into_transaction_request(), block.to())
+                    .await?;
 
-                // handle nonce error, gas error correctly
                 match self.send_transaction(tx_req).await {
-                    Ok(_) => {
+                    Ok(landed) => {
                         tracing::info!("Posted sp1 proof for batch:{}", block);
+                        Ok(Some(landed))
                     }
                     Err(e) => {
                         tracing::error!(
@@ -123,53 +152,136 @@ impl L1Transactions for EVMProvider {
                             block,
                             e.to_string()
                         );
-                        return Err(e);
+                        Err(e)
                     }
                 }
             }
             PostParams::Dummy(_dummy_params, _) => {
                 tracing::warn!("Dummy chain: Mock txn submission successful");
+                Ok(None)
             }
         }
-        Ok(())
     }
 }
 
 impl EVMProvider {
-    /// This function keeps on running unless the transaction is successful.
-    /// If failed, it tries to do the transaction again, waiting for 15 seconds. This runs in infinite loop.
-    pub async fn send_transaction(&self, transaction: TransactionRequest) -> Result<String> {
-        let mut attempt = 0;
-        loop {
-            let pending_tx = self.provider.send_transaction(transaction.clone()).await?;
-
-            tracing::debug!("Pending transaction hash: {}", pending_tx.tx_hash());
-            match pending_tx.get_receipt().await {
-                Ok(receipt) => {
-                    let txn_hash = receipt.transaction_hash.to_string();
-                    tracing::info!("Transaction Submitted! txn_hash: {}", txn_hash);
-                    return Ok(txn_hash);
-                }
-                Err(e) => {
-                    attempt += 1;
-                    if attempt >= MAX_RETRIES {
-                        tracing::error!(
-                            "Transaction failed after {} attempts. Error: {}",
-                            attempt,
-                            e
-                        );
-                        return Err(e.into());
-                    }
+    /// Runs the transaction with `eth_call` and estimates its gas before it is broadcast.
+    /// A revert is decoded against the contract errors, so the poster can decide what to do with it.
+    pub async fn simulate(
+        &self,
+        transaction: TransactionRequest,
+        batch: u64,
+    ) -> Result<TransactionRequest> {
+        // the wallet only fills `from` on send, the contract checks the caller in simulation too
+        let transaction = transaction.with_from(self.rpcs.primary().default_signer_address());
 
-                    tracing::error!(
-                        "Transaction Failed! Error: {}. Retrying ({}/{}) in 15 seconds.",
-                        e,
-                        attempt,
-                        MAX_RETRIES
-                    );
-                    sleep(Duration::from_secs(15)).await;
+        // only transport failures fail over, an error response is the answer of the node
+        let call_result = self
+            .rpcs
+            .failover(|provider| {
+                let tx = transaction.clone();
+                async move {
+                    match provider.call(&tx).await {
+                        Ok(_) => Ok(Ok(())),
+                        Err(RpcError::ErrorResp(payload)) => Ok(Err(payload)),
+                        Err(e) => Err(e),
+                    }
                 }
-            }
+            })
+            .await?;
+
+        if let Err(payload) = call_result {
+            return Err(match payload.as_revert_data() {
+                Some(data) => decode_revert(&data, batch),
+                None => AggregatorError::TransactionReverted(payload.to_string()).into(),
+            });
+        }
+
+        let gas = self
+            .rpcs
+            .failover(|provider| {
+                let tx = transaction.clone();
+                async move { provider.estimate_gas(&tx).await }
+            })
+            .await?;
+        tracing::debug!("Simulation successful. batch: {} gas: {}", batch, gas);
+
+        // leave headroom in case the state changes before the transaction lands
+        Ok(transaction.with_gas_limit(gas + gas / 5))
+    }
+
+    /// Sends the transaction through the tx manager, which replaces it with bumped fees
+    /// if it gets stuck and fills nonce gaps left by earlier runs.
+    /// A receipt with a failed status is returned as an error.
+    pub async fn send_transaction(&self, transaction: TransactionRequest) -> Result<LandedTx> {
+        let (receipt, broadcasts) = self.tx_manager.send(&self.rpcs, transaction).await?;
+        let txn_hash = receipt.transaction_hash.to_string();
+
+        if !receipt.status() {
+            tracing::error!("Transaction reverted! txn_hash: {}", txn_hash);
+            return Err(AggregatorError::TransactionReverted(txn_hash).into());
         }
+
+        tracing::info!(
+            "Transaction Submitted! txn_hash: {} block: {:?} gas_used: {} broadcasts: {}",
+            txn_hash,
+            receipt.block_number,
+            receipt.gas_used,
+            broadcasts
+        );
+        Ok(LandedTx {
+            tx_hash: txn_hash,
+            block_number: receipt.block_number.unwrap_or_default(),
+            block_hash: receipt.block_hash.unwrap_or_default(),
+            gas_used: receipt.gas_used as u64,
+            effective_gas_price: receipt.effective_gas_price,
+            broadcasts,
+        })
+    }
+}
+
+/// Maps revert data to the matching aggregator error
+pub fn decode_revert(data: &[u8], batch: u64) -> anyhow::Error {
+    let error = match TwineChainErrors::abi_decode(data, true) {
+        Ok(TwineChainErrors::InvalidProof(_)) => AggregatorError::InvalidProof(batch),
+        Ok(TwineChainErrors::InvalidBatchNumber(e)) => AggregatorError::WrongBatchNumber {
+            expected: e.expected,
+            provided: e.provided,
+        },
+        Ok(TwineChainErrors::BatchAlreadyCommitted(e)) => {
+            AggregatorError::BatchAlreadyCommitted(e.batchNumber)
+        }
+        Ok(TwineChainErrors::BatchAlreadyFinalized(e)) => {
+            AggregatorError::BatchAlreadyFinalized(e.batchNumber)
+        }
+        Ok(TwineChainErrors::BatchNotCommitted(e)) => {
+            AggregatorError::BatchNotCommitted(e.batchNumber)
+        }
+        Err(_) => AggregatorError::TransactionReverted(
+            decode_revert_reason(data).unwrap_or_else(|| data.encode_hex()),
+        ),
+    };
+    error.into()
+}
+
+#[cfg(test)]
+mod test {
+    use alloy::sol_types::SolError;
+
+    use super::{decode_revert, TwineChain};
+    use crate::error::{post_action, AggregatorError, PostAction};
+
+    #[test]
+    fn test_decode_contract_errors() {
+        let data = TwineChain::BatchAlreadyFinalized { batchNumber: 7 }.abi_encode();
+        let error = decode_revert(&data, 7);
+        assert!(matches!(
+            error.downcast_ref::<AggregatorError>(),
+            Some(AggregatorError::BatchAlreadyFinalized(7))
+        ));
+        assert_eq!(post_action(&error), PostAction::Skip);
+
+        let data = TwineChain::InvalidProof {}.abi_encode();
+        assert_eq!(post_action(&decode_revert(&data, 7)), PostAction::Alert);
     }
 }
@@ -0,0 +1,206 @@
+use alloy::{
+    consensus::{SignableTransaction, Transaction, TxEnvelope},
+    eips::eip2718::Decodable2718,
+    network::{EthereumWallet, TransactionBuilder, TxSigner},
+    primitives::{Address, Bytes, Signature},
+    rpc::{
+        client::{ClientBuilder, RpcClient},
+        types::TransactionRequest,
+    },
+    signers::local::PrivateKeySigner,
+    transports::http::{Client, Http},
+};
+use anyhow::{Context, Error, Result};
+use async_trait::async_trait;
+
+use crate::config::SignerConfig;
+
+/// Builds the wallet that signs settlement transactions for an L1
+pub fn make_wallet(signer: &SignerConfig) -> Result<EthereumWallet> {
+    match signer {
+        SignerConfig::Keystore {
+            path,
+            password_file,
+            password_env,
+        } => {
+            let password = keystore_password(password_file.as_deref(), password_env.as_deref())?;
+            let signer = PrivateKeySigner::decrypt_keystore(path, password)
+                .with_context(|| format!("Error decrypting keystore {}", path))?;
+            Ok(EthereumWallet::from(signer))
+        }
+        SignerConfig::Remote { url, address } => {
+            let address: Address = address
+                .parse()
+                .with_context(|| format!("Invalid remote signer address {}", address))?;
+            Ok(EthereumWallet::new(RemoteSigner::new(url, address)?))
+        }
+        SignerConfig::Raw {
+            private_key,
+            allow_raw_key,
+        } => {
+            if !allow_raw_key {
+                return Err(Error::msg(
+                    "raw private keys are disabled, set allow_raw_key to use one for development",
+                ));
+            }
+            tracing::warn!(
+                "Using a raw private key from the config file. Only use this for development"
+            );
+            let signer: PrivateKeySigner = private_key
+                .expose()
+                .trim_start_matches("0x")
+                .parse()
+                .with_context(|| "Error parsing private key")?;
+            Ok(EthereumWallet::from(signer))
+        }
+    }
+}
+
+fn keystore_password(password_file: Option<&str>, password_env: Option<&str>) -> Result<String> {
+    match (password_file, password_env) {
+        (Some(file), _) => Ok(std::fs::read_to_string(file)
+            .with_context(|| format!("Error reading keystore password file {}", file))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string()),
+        (None, Some(var)) => std::env::var(var)
+            .with_context(|| format!("Keystore password variable {} is not set", var)),
+        (None, None) => Err(Error::msg("keystore needs a password_file or password_env")),
+    }
+}
+
+/// Signer backed by a remote service that answers `eth_signTransaction` for one account.
+/// The returned transaction is checked to be the one that was asked for, signed by that account.
+#[derive(Clone)]
+pub struct RemoteSigner {
+    address: Address,
+    client: RpcClient<Http<Client>>,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, address: Address) -> Result<Self> {
+        let url = url
+            .parse()
+            .with_context(|| format!("Invalid remote signer URL {}", url))?;
+        Ok(Self {
+            address,
+            client: ClientBuilder::default().http(url),
+        })
+    }
+}
+
+#[async_trait]
+impl TxSigner<Signature> for RemoteSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: &mut dyn SignableTransaction<Signature>,
+    ) -> alloy::signers::Result<Signature> {
+        let request = signing_request(self.address, tx);
+        let raw: Bytes = self
+            .client
+            .request("eth_signTransaction", (request,))
+            .await
+            .map_err(alloy::signers::Error::other)?;
+
+        let signed =
+            TxEnvelope::decode_2718(&mut raw.as_ref()).map_err(alloy::signers::Error::other)?;
+        if signed.signature_hash() != tx.signature_hash() {
+            return Err(alloy::signers::Error::other(
+                "remote signer returned a different transaction",
+            ));
+        }
+        let signer = signed
+            .recover_signer()
+            .map_err(alloy::signers::Error::other)?;
+        if signer != self.address {
+            return Err(alloy::signers::Error::other(format!(
+                "remote signer signed with {} instead of {}",
+                signer, self.address
+            )));
+        }
+        Ok(*signed.signature())
+    }
+}
+
+/// `eth_signTransaction` request with every field of the transaction to sign
+fn signing_request(from: Address, tx: &dyn SignableTransaction<Signature>) -> TransactionRequest {
+    let mut request = TransactionRequest::default()
+        .with_from(from)
+        .with_nonce(tx.nonce())
+        .with_gas_limit(tx.gas_limit())
+        .with_value(tx.value())
+        .with_input(Bytes::copy_from_slice(tx.input()))
+        .transaction_type(tx.ty());
+    request.to = Some(tx.to());
+    request.chain_id = tx.chain_id();
+    request.access_list = tx.access_list().cloned();
+    match tx.gas_price() {
+        Some(gas_price) => request.gas_price = Some(gas_price),
+        None => {
+            request.max_fee_per_gas = Some(tx.max_fee_per_gas());
+            request.max_priority_fee_per_gas = tx.max_priority_fee_per_gas();
+        }
+    }
+    request
+}
+
+#[cfg(test)]
+mod test {
+    use alloy::{
+        consensus::{SignableTransaction, TxEip1559},
+        eips::eip2718::Encodable2718,
+        network::{EthereumWallet, TransactionBuilder, TxSigner},
+        primitives::{Address, Bytes, TxKind, U256},
+        rpc::types::TransactionRequest,
+        signers::local::PrivateKeySigner,
+    };
+    use jsonrpsee::{server::Server, types::ErrorObjectOwned, RpcModule};
+
+    use super::RemoteSigner;
+
+    #[tokio::test]
+    async fn test_remote_signer() {
+        // stand-in for the signing service, holding the key locally
+        let key = PrivateKeySigner::random();
+        let address = key.address();
+        let server = Server::builder().build("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+
+        let mut module = RpcModule::new(EthereumWallet::from(key));
+        module
+            .register_async_method("eth_signTransaction", |params, wallet, _| async move {
+                let request: TransactionRequest = params.one()?;
+                let signed = request
+                    .build(wallet.as_ref())
+                    .await
+                    .map_err(|e| ErrorObjectOwned::owned(-32000, e.to_string(), None::<()>))?;
+                Ok::<_, ErrorObjectOwned>(Bytes::from(signed.encoded_2718()))
+            })
+            .unwrap();
+        let _handle = server.start(module);
+
+        let mut tx = TxEip1559 {
+            chain_id: 1,
+            nonce: 3,
+            gas_limit: 21_000,
+            max_fee_per_gas: 2_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+            to: TxKind::Call(Address::ZERO),
+            value: U256::from(1),
+            ..Default::default()
+        };
+        let remote = RemoteSigner::new(&url, address).unwrap();
+        let signature = remote.sign_transaction(&mut tx).await.unwrap();
+        let recovered = signature
+            .recover_address_from_prehash(&tx.signature_hash())
+            .unwrap();
+        assert_eq!(recovered, address);
+
+        // a service signing with another account is rejected
+        let remote = RemoteSigner::new(&url, Address::ZERO).unwrap();
+        assert!(remote.sign_transaction(&mut tx).await.is_err());
+    }
+}
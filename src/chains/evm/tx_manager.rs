@@ -0,0 +1,493 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, TxHash, U256},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+    transports::{RpcError, TransportErrorKind},
+};
+use alloy_provider::{Provider, WalletProvider};
+use anyhow::{Error, Result};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{parse_gwei, GasConfig},
+    error::AggregatorError,
+};
+
+use super::endpoints::RpcPool;
+
+static MAX_RETRIES: i32 = 10;
+
+/// Gas limit of a plain value transfer, used for cancel transactions
+static TRANSFER_GAS: u64 = 21_000;
+
+/// JSON-RPC error code of a stale nonce on Besu
+static NONCE_TOO_LOW_CODE: i64 = -32001;
+/// How clients word a stale nonce, lowercased
+static NONCE_TOO_LOW: [&str; 3] = ["nonce too low", "nonce is too low", "oldnonce"];
+
+/// Sends transactions for a single L1 signer. Nonces are assigned here instead of by the
+/// provider fillers, so that a stuck transaction can be replaced at the same nonce with higher fees.
+#[derive(Clone)]
+pub struct TxManager {
+    pub gas: GasConfig,
+    max_fee_ceiling: Option<u128>,
+    priority_fee_ceiling: Option<u128>,
+    nonces: Arc<Mutex<NonceState>>,
+}
+
+#[derive(Debug, Default)]
+struct NonceState {
+    /// Next nonce to use for the signer. None until it is read from the chain.
+    next: Option<u64>,
+    /// Hashes broadcast at each nonce not yet seen mined
+    sent: BTreeMap<u64, Vec<TxHash>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fees {
+    /// gas price in legacy mode
+    max_fee_per_gas: u128,
+    max_priority_fee_per_gas: u128,
+}
+
+impl TxManager {
+    pub fn new(gas: GasConfig) -> Result<Self> {
+        let max_fee_ceiling = gas.max_fee_per_gas.as_deref().map(parse_gwei).transpose()?;
+        let priority_fee_ceiling = gas
+            .max_priority_fee_per_gas
+            .as_deref()
+            .map(parse_gwei)
+            .transpose()?;
+
+        Ok(Self {
+            gas,
+            max_fee_ceiling,
+            priority_fee_ceiling,
+            nonces: Arc::new(Mutex::new(NonceState::default())),
+        })
+    }
+
+    /// Sends the transaction and waits for its receipt. If no receipt arrives within the resend interval,
+    /// the transaction is replaced at the same nonce with bumped fees, up to the configured ceilings.
+    /// Transactions are sent one at a time per signer, so a nonce is never skipped.
+    /// Returns the receipt with the number of times the transaction was broadcast.
+    pub async fn send(
+        &self,
+        rpcs: &RpcPool,
+        transaction: TransactionRequest,
+    ) -> Result<(TransactionReceipt, u32)> {
+        let mut nonces = self.nonces.lock().await;
+        let nonce = self.recover_nonce(rpcs, &mut nonces).await?;
+
+        let mut fees = self.estimate_fees(rpcs).await?;
+        let mut sent_hashes: Vec<TxHash> = vec![];
+
+        for attempt in 1..=MAX_RETRIES {
+            // an earlier broadcast may have landed while the replacement was prepared
+            if let Some(receipt) = find_receipt(rpcs, &sent_hashes).await {
+                nonces.next = Some(nonce + 1);
+                nonces.sent.remove(&nonce);
+                return Ok((receipt, sent_hashes.len() as u32));
+            }
+
+            let tx = self.with_fees(transaction.clone().with_nonce(nonce), fees);
+            let sent = rpcs
+                .failover(|provider| {
+                    let tx = tx.clone();
+                    async move { provider.send_transaction(tx).await }
+                })
+                .await;
+            match sent {
+                Ok(pending_tx) => {
+                    let tx_hash = *pending_tx.tx_hash();
+                    tracing::debug!(
+                        "Pending transaction hash: {} nonce: {} fees: {:?}",
+                        tx_hash,
+                        nonce,
+                        fees
+                    );
+                    sent_hashes.push(tx_hash);
+                    nonces.sent.entry(nonce).or_default().push(tx_hash);
+                    nonces.next = Some(nonce + 1);
+
+                    // on websocket and ipc endpoints the receipt wait is driven by a new block subscription
+                    match pending_tx
+                        .with_timeout(Some(Duration::from_secs(self.gas.resend_interval)))
+                        .get_receipt()
+                        .await
+                    {
+                        Ok(receipt) => {
+                            nonces.sent.remove(&nonce);
+                            return Ok((receipt, sent_hashes.len() as u32));
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "No receipt for txn_hash: {} ({}/{}). error: {}",
+                                tx_hash,
+                                attempt,
+                                MAX_RETRIES,
+                                e
+                            );
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to send transaction nonce: {} ({}/{}). error: {}",
+                        nonce,
+                        attempt,
+                        MAX_RETRIES,
+                        e
+                    );
+                    if sent_hashes.is_empty() && is_stale_nonce(&e) {
+                        // the cached nonce is stale, read it again on the next send
+                        nonces.next = None;
+                        return Err(e.into());
+                    }
+                }
+            }
+
+            fees = self.bump(fees);
+        }
+
+        if let Some(receipt) = find_receipt(rpcs, &sent_hashes).await {
+            nonces.sent.remove(&nonce);
+            return Ok((receipt, sent_hashes.len() as u32));
+        }
+        Err(AggregatorError::SubmitTransactionFailed(format!(
+            "No receipt after {} attempts at nonce {}",
+            MAX_RETRIES, nonce
+        ))
+        .into())
+    }
+
+    /// Returns the nonce for the next transaction. Nonces this manager broadcast at that are no longer
+    /// known to any node, and gaps the node has nothing at, are replaced with cancel transactions so that
+    /// the next transaction is not queued behind them. Transactions still in the mempool are left to land.
+    async fn recover_nonce(&self, rpcs: &RpcPool, state: &mut NonceState) -> Result<u64> {
+        let signer = rpcs.primary().default_signer_address();
+        let mined = mined_nonce(rpcs, signer).await?;
+        let pending = rpcs
+            .quorum_read(|provider| async move {
+                provider.get_transaction_count(signer).pending().await
+            })
+            .await?;
+        state.sent = state.sent.split_off(&mined);
+        let next = state.next.unwrap_or(pending).max(pending);
+        if next <= mined {
+            return Ok(next);
+        }
+
+        let mut seen = HashSet::new();
+        for hash in state.sent.values().flatten() {
+            if in_mempool(rpcs, *hash).await {
+                seen.insert(*hash);
+            }
+        }
+        let dropped = dropped_nonces(mined, pending, next, &state.sent, &seen);
+        tracing::warn!(
+            "Unmined transactions found for signer: {} nonces: {}..{} dropped: {:?}",
+            signer,
+            mined,
+            next,
+            dropped
+        );
+        for nonce in dropped {
+            self.cancel(rpcs, nonce).await?;
+            state.sent.remove(&nonce);
+        }
+        Ok(next)
+    }
+
+    /// Replaces whatever is at the nonce with a zero value transfer to self
+    async fn cancel(&self, rpcs: &RpcPool, nonce: u64) -> Result<()> {
+        let signer = rpcs.primary().default_signer_address();
+        let cancel_tx = TransactionRequest::default()
+            .with_from(signer)
+            .with_to(signer)
+            .with_value(U256::ZERO)
+            .with_gas_limit(TRANSFER_GAS.into())
+            .with_nonce(nonce);
+
+        // the stuck transaction was sent with unknown fees, start above the current estimate
+        let mut fees = self.bump(self.estimate_fees(rpcs).await?);
+        for attempt in 1..=MAX_RETRIES {
+            if mined_nonce(rpcs, signer).await? > nonce {
+                tracing::info!("Nonce {} no longer stuck", nonce);
+                return Ok(());
+            }
+
+            let tx = self.with_fees(cancel_tx.clone(), fees);
+            let sent = rpcs
+                .failover(|provider| {
+                    let tx = tx.clone();
+                    async move { provider.send_transaction(tx).await }
+                })
+                .await;
+            match sent {
+                Ok(pending_tx) => {
+                    tracing::info!(
+                        "Cancel transaction sent. nonce: {} txn_hash: {}",
+                        nonce,
+                        pending_tx.tx_hash()
+                    );
+                    if pending_tx
+                        .with_timeout(Some(Duration::from_secs(self.gas.resend_interval)))
+                        .get_receipt()
+                        .await
+                        .is_ok()
+                    {
+                        return Ok(());
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to send cancel transaction nonce: {} ({}/{}). error: {}",
+                        nonce,
+                        attempt,
+                        MAX_RETRIES,
+                        e
+                    );
+                }
+            }
+            fees = self.bump(fees);
+        }
+
+        Err(AggregatorError::SubmitTransactionFailed(format!(
+            "Failed to cancel stuck transaction at nonce {}",
+            nonce
+        ))
+        .into())
+    }
+
+    async fn estimate_fees(&self, rpcs: &RpcPool) -> Result<Fees> {
+        let fees = if self.gas.legacy {
+            let gas_price = rpcs
+                .failover(|provider| async move { provider.get_gas_price().await })
+                .await?;
+            Fees {
+                max_fee_per_gas: gas_price,
+                max_priority_fee_per_gas: 0,
+            }
+        } else {
+            let estimate = rpcs
+                .failover(|provider| async move { provider.estimate_eip1559_fees(None).await })
+                .await?;
+            Fees {
+                max_fee_per_gas: estimate.max_fee_per_gas,
+                max_priority_fee_per_gas: estimate.max_priority_fee_per_gas,
+            }
+        };
+        Ok(self.cap(fees))
+    }
+
+    fn bump(&self, fees: Fees) -> Fees {
+        let percent = 100 + self.gas.bump_percent as u128;
+        let bumped = Fees {
+            max_fee_per_gas: fees.max_fee_per_gas * percent / 100,
+            max_priority_fee_per_gas: fees.max_priority_fee_per_gas * percent / 100,
+        };
+        let capped = self.cap(bumped);
+        if capped.max_fee_per_gas == fees.max_fee_per_gas {
+            tracing::warn!(
+                "Fee ceiling reached, resending without a bump. max_fee_per_gas: {}",
+                capped.max_fee_per_gas
+            );
+        }
+        capped
+    }
+
+    fn cap(&self, fees: Fees) -> Fees {
+        let max_fee_per_gas = match self.max_fee_ceiling {
+            Some(ceiling) => fees.max_fee_per_gas.min(ceiling),
+            None => fees.max_fee_per_gas,
+        };
+        let max_priority_fee_per_gas = match self.priority_fee_ceiling {
+            Some(ceiling) => fees.max_priority_fee_per_gas.min(ceiling),
+            None => fees.max_priority_fee_per_gas,
+        };
+        Fees {
+            max_fee_per_gas,
+            // priority fee can never be above the max fee
+            max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_fee_per_gas),
+        }
+    }
+
+    fn with_fees(&self, mut tx: TransactionRequest, fees: Fees) -> TransactionRequest {
+        if self.gas.legacy {
+            tx.max_fee_per_gas = None;
+            tx.max_priority_fee_per_gas = None;
+            tx.gas_price = Some(fees.max_fee_per_gas);
+        } else {
+            tx.gas_price = None;
+            tx.max_fee_per_gas = Some(fees.max_fee_per_gas);
+            tx.max_priority_fee_per_gas = Some(fees.max_priority_fee_per_gas);
+        }
+        tx
+    }
+}
+
+/// Returns the receipt of the first of the hashes that has been mined. A failed lookup counts as
+/// not mined yet, so the caller keeps bumping at the same nonce instead of giving up on it.
+async fn find_receipt(rpcs: &RpcPool, hashes: &[TxHash]) -> Option<TransactionReceipt> {
+    first_receipt(hashes, |hash| {
+        rpcs.quorum_read(
+            move |provider| async move { provider.get_transaction_receipt(hash).await },
+        )
+    })
+    .await
+}
+
+async fn first_receipt<T, F, Fut>(hashes: &[TxHash], lookup: F) -> Option<T>
+where
+    F: Fn(TxHash) -> Fut,
+    Fut: Future<Output = Result<Option<T>>>,
+{
+    for hash in hashes {
+        match lookup(*hash).await {
+            Ok(Some(receipt)) => return Some(receipt),
+            Ok(None) => {}
+            Err(e) => tracing::warn!(
+                "Receipt lookup failed, treating as not mined. txn_hash: {} error: {}",
+                hash,
+                e
+            ),
+        }
+    }
+    None
+}
+
+/// Whether the nodes still know the transaction. A failed lookup counts as known, so a transaction
+/// is only cancelled when it is known to be dropped.
+async fn in_mempool(rpcs: &RpcPool, hash: TxHash) -> bool {
+    rpcs.quorum_read(|provider| async move { provider.get_transaction_by_hash(hash).await })
+        .await
+        .map_or(true, |tx| tx.is_some())
+}
+
+/// Unmined nonces in `mined..next` that need a cancel: those whose every broadcast was dropped, and
+/// those at or above the pending nonce that nothing was broadcast at. Untracked nonces below the
+/// pending nonce hold transactions of an earlier run still in the mempool.
+fn dropped_nonces(
+    mined: u64,
+    pending: u64,
+    next: u64,
+    sent: &BTreeMap<u64, Vec<TxHash>>,
+    seen: &HashSet<TxHash>,
+) -> Vec<u64> {
+    (mined..next)
+        .filter(|nonce| match sent.get(nonce) {
+            Some(hashes) => !hashes.iter().any(|hash| seen.contains(hash)),
+            None => *nonce >= pending,
+        })
+        .collect()
+}
+
+/// Whether the node refused the transaction because its nonce is already used
+fn is_stale_nonce(e: &Error) -> bool {
+    let code = e
+        .downcast_ref::<RpcError<TransportErrorKind>>()
+        .and_then(RpcError::as_error_resp)
+        .map(|payload| payload.code);
+    let message = e.to_string().to_lowercase();
+    code == Some(NONCE_TOO_LOW_CODE) || NONCE_TOO_LOW.iter().any(|m| message.contains(m))
+}
+
+/// Number of mined transactions of the signer
+async fn mined_nonce(rpcs: &RpcPool, signer: Address) -> Result<u64> {
+    rpcs.quorum_read(
+        |provider| async move { provider.get_transaction_count(signer).latest().await },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashSet};
+
+    use alloy::primitives::TxHash;
+    use anyhow::Error;
+
+    use super::{dropped_nonces, first_receipt, is_stale_nonce, TxManager};
+    use crate::config::GasConfig;
+
+    #[test]
+    fn test_bump_respects_ceiling() {
+        let manager = TxManager::new(GasConfig {
+            max_fee_per_gas: Some("30".to_string()),
+            max_priority_fee_per_gas: Some("2".to_string()),
+            ..GasConfig::default()
+        })
+        .unwrap();
+        let fees = manager.cap(super::Fees {
+            max_fee_per_gas: 20_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+        });
+
+        let bumped = manager.bump(fees);
+        assert_eq!(bumped.max_fee_per_gas, 23_000_000_000);
+        assert_eq!(bumped.max_priority_fee_per_gas, 1_150_000_000);
+
+        let mut fees = bumped;
+        for _ in 0..10 {
+            fees = manager.bump(fees);
+        }
+        assert_eq!(fees.max_fee_per_gas, 30_000_000_000);
+        assert_eq!(fees.max_priority_fee_per_gas, 2_000_000_000);
+        assert!(TxManager::new(GasConfig {
+            max_fee_per_gas: Some("thirty".to_string()),
+            ..GasConfig::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_recover_cancels_only_dropped_nonces() {
+        let [landing, dropped, replaced, bumped] = [1, 2, 3, 4].map(TxHash::repeat_byte);
+        let sent = BTreeMap::from([
+            (5, vec![landing]),
+            (6, vec![dropped]),
+            (7, vec![replaced, bumped]),
+        ]);
+        let seen = HashSet::from([landing, bumped]);
+
+        // 5 is still in the mempool, 7 has a live replacement, 8 is a gap above the pending nonce
+        assert_eq!(dropped_nonces(5, 8, 9, &sent, &seen), [6, 8]);
+        // nonces left by an earlier run below the pending nonce are not ours to cancel
+        assert!(dropped_nonces(2, 5, 5, &BTreeMap::new(), &seen).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_receipt_errors_count_as_not_mined() {
+        let [failing, mined, missing] = [1, 2, 3].map(TxHash::repeat_byte);
+        let lookup = |hash: TxHash| async move {
+            match hash {
+                h if h == failing => Err(Error::msg("connection reset")),
+                h if h == mined => Ok(Some(hash)),
+                _ => Ok(None),
+            }
+        };
+
+        assert_eq!(first_receipt(&[failing, mined], lookup).await, Some(mined));
+        assert_eq!(first_receipt(&[failing, missing], lookup).await, None);
+    }
+
+    #[test]
+    fn test_stale_nonce_wordings() {
+        for message in [
+            "nonce too low: next nonce 5, tx nonce 4",
+            "Nonce too low",
+            "InvalidTransaction: OldNonce",
+        ] {
+            assert!(is_stale_nonce(&Error::msg(message)), "{}", message);
+        }
+        assert!(!is_stale_nonce(&Error::msg("insufficient funds for gas")));
+    }
+}
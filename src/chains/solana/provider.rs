@@ -1,40 +1,73 @@
 use crate::chains::{
-    chains::{BalanceProvider, FetchL2TransactionData, L1Transactions},
+    chains::{BalanceProvider, FetchL2TransactionData, L1State, L1Transactions, LandedTx},
     evm::sender::TwineChain::CommitBatchInfo,
 };
-use alloy_primitives::U256;
-use anyhow::Result;
+use alloy_primitives::{FixedBytes, U256};
+use anyhow::{Error, Result};
 
 #[derive(Clone)]
 pub struct SolanaProvider {}
 
 impl BalanceProvider for SolanaProvider {
     async fn query_balance(&self) -> Result<U256> {
-        todo!()
+        Err(unsupported())
     }
 
     async fn balance_under_threshold(&self, threshold: U256) -> Result<(bool, String)> {
         let _ = threshold;
-        todo!()
+        Err(unsupported())
     }
 }
 
 impl L1Transactions for SolanaProvider {
-    async fn submit_proof(&self, params: crate::types::PostParams) -> Result<()> {
+    async fn submit_proof(&self, params: crate::types::PostParams) -> Result<Option<LandedTx>> {
         let _ = params;
-        todo!()
+        Err(unsupported())
     }
 
-    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<()> {
+    async fn commit_batch(&self, params: CommitBatchInfo, height: u64) -> Result<Option<LandedTx>> {
         let _ = height;
         let _ = params;
-        todo!()
+        Err(unsupported())
+    }
+}
+
+impl L1State for SolanaProvider {
+    async fn last_committed_batch(&self) -> Result<u64> {
+        Err(unsupported())
+    }
+
+    async fn last_finalized_batch(&self) -> Result<u64> {
+        Err(unsupported())
+    }
+
+    async fn settled_batch(&self) -> Result<u64> {
+        Err(unsupported())
+    }
+
+    async fn state_root(&self, batch: u64) -> Result<FixedBytes<32>> {
+        let _ = batch;
+        Err(unsupported())
+    }
+
+    async fn finalized_height(&self) -> Result<u64> {
+        Err(unsupported())
+    }
+
+    async fn tx_inclusion(&self, tx_hash: &str) -> Result<Option<LandedTx>> {
+        let _ = tx_hash;
+        Err(unsupported())
     }
 }
 
 impl FetchL2TransactionData for SolanaProvider {
     async fn fetch_commit_batch(&self, height: u64) -> Result<CommitBatchInfo> {
         let _ = height;
-        todo!()
+        Err(unsupported())
     }
 }
+
+/// Solana settlement is not implemented yet, every call fails instead of panicking
+fn unsupported() -> Error {
+    Error::msg("solana not supported")
+}
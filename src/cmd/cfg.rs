@@ -1,12 +1,18 @@
 use crate::{
     aggregator::run,
     chains::{
-        chains::{make_l2_provider, FetchL2TransactionData, L1Transactions},
+        chains::{make_l2_provider, ChainProviders, FetchL2TransactionData},
         evm::provider::{EVMProvider, EVMProviderConfig},
     },
     config::Config,
-    database::db::DB,
+    database::{
+        archive::{export, import, LAST_HEIGHT},
+        db::{Collection, DeleteScope, DB},
+        storage::database_name,
+    },
+    poster::poster::settle_batch,
     types::SupportedProvers,
+    utils::secret::reveal_secrets,
     verifier::{dummy::Dummy, risc0::RISC0, sp1::SP1, verifier::ProofTraits},
 };
 use anyhow::Result;
@@ -14,12 +20,16 @@ use clap::{Parser, Subcommand};
 use home::home_dir;
 use std::{
     fs::File,
-    io::Read,
-    path::PathBuf,
+    io::{Read, Write},
+    path::{Path, PathBuf},
     process::{self},
 };
 
-use super::logger::logging;
+use super::{
+    layers::{load_layered, ConfigSources},
+    logger::logging,
+    validate::deep_validate,
+};
 
 const DEFAULT_CONFIG_DIR: &str = ".twine/aggregator/config.yaml";
 
@@ -37,8 +47,42 @@ pub struct Args {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Run,
-    Show,
-    DeleteDB,
+    Show {
+        /// Print keys, passwords and connection strings instead of redacting them
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Delete stored data, everything unless scoped. Asks for the database name to confirm.
+    DeleteDB {
+        /// Skip the confirmation prompt
+        #[arg(long)]
+        yes: bool,
+
+        /// First height to delete
+        #[arg(long)]
+        from: Option<u64>,
+
+        /// Last height to delete
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Only delete this collection
+        #[arg(long, value_enum)]
+        collection: Option<Collection>,
+
+        /// Only delete heights that are not settled on every L1
+        #[arg(long)]
+        unsettled: bool,
+
+        /// Export the heights to this directory before deleting. The submission log is not exported.
+        #[arg(long)]
+        export: Option<PathBuf>,
+    },
+    Validate {
+        /// Also check the chains: chain ids, contract code, signers and balances, and the SP1 vkey
+        #[arg(long)]
+        deep: bool,
+    },
     ManualRelay {
         #[arg(short, long)]
         height: u64,
@@ -59,6 +103,31 @@ pub enum Commands {
         #[arg(short, long)]
         proof_json: PathBuf,
     },
+    /// Write proofs, threshold state and settlements to a directory as JSONL with a manifest.
+    /// The submission log is not part of an export.
+    Export {
+        /// First height to export
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+
+        /// Last height to export, the latest one by default
+        #[arg(long)]
+        to: Option<u64>,
+
+        #[arg(short, long)]
+        dir: PathBuf,
+    },
+    /// Restore an export into the configured database
+    Import {
+        #[arg(short, long)]
+        dir: PathBuf,
+    },
+    /// Apply the retention policy now
+    Prune {
+        /// List what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn default_config_path() -> PathBuf {
@@ -84,10 +153,12 @@ pub async fn init() -> Result<()> {
     };
 
     let cfg;
+    let sources;
 
-    match load_and_validate_config(path) {
-        Ok(config) => {
+    match load_and_validate_config(path.clone()) {
+        Ok((config, config_sources)) => {
             cfg = config;
+            sources = config_sources;
             // Set up logging
             logging(&cfg.global.logging);
             tracing::info!("Config ok!");
@@ -99,9 +170,25 @@ pub async fn init() -> Result<()> {
     };
 
     match &cli.command {
-        Commands::Run => handle_run_command(cfg).await,
-        Commands::Show => handle_show_command(cfg),
-        Commands::DeleteDB => delete_db(cfg).await,
+        Commands::Run => handle_run_command(cfg, path).await,
+        Commands::Show { reveal } => handle_show_command(cfg, sources, *reveal),
+        Commands::DeleteDB {
+            yes,
+            from,
+            to,
+            collection,
+            unsettled,
+            export,
+        } => {
+            let scope = DeleteScope {
+                from: *from,
+                to: *to,
+                collection: *collection,
+                unsettled: *unsettled,
+            };
+            delete_db(cfg, scope, *yes, export.as_deref()).await
+        }
+        Commands::Validate { deep } => handle_validate_command(cfg, *deep).await,
         Commands::ManualRelay {
             height,
             chain,
@@ -115,6 +202,11 @@ pub async fn init() -> Result<()> {
             proof_type,
             proof_json,
         } => print_public_values(proof_type, proof_json),
+        Commands::Export { from, to, dir } => {
+            handle_export_command(cfg, *from, to.unwrap_or(LAST_HEIGHT), dir).await
+        }
+        Commands::Import { dir } => handle_import_command(cfg, dir).await,
+        Commands::Prune { dry_run } => handle_prune_command(cfg, *dry_run).await,
     }
 }
 
@@ -144,32 +236,20 @@ pub async fn manual_proof_relay(
     let destination = l1_chains.get(chain).expect("Invalid chain name");
 
     let l2_chain = cfg.l2;
-    let l2_provider = make_l2_provider(l2_chain);
+    let l2_provider = make_l2_provider(l2_chain).await;
     match destination {
-        crate::config::L1Details::Solana(_solana_config) => todo!(),
+        crate::config::L1Details::Solana(_solana_config) => {
+            tracing::error!("Solana is not supported. chain:{}", chain);
+        }
         crate::config::L1Details::EVM(evmconfig) => {
-            let provider_config = EVMProviderConfig::new(
-                evmconfig.rpc.clone(),
-                evmconfig.private_key.clone(),
-                evmconfig.contract.clone(),
-            );
-            let l1_provider = EVMProvider::new(provider_config);
+            let provider_config = EVMProviderConfig::from_evm_config(evmconfig);
+            let l1_provider = ChainProviders::EVM(EVMProvider::new(provider_config).await);
 
             let commit_batch_info = l2_provider
                 .fetch_commit_batch(*height)
                 .await
                 .expect("Failed to construct commit batch info");
 
-            match l1_provider.commit_batch(commit_batch_info, *height).await {
-                Ok(_) => {
-                    println!("Commit batch successful");
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    panic!("Commit failed");
-                }
-            }
-
             let post_params =
                 match SupportedProvers::from_str(proof_type).expect("Invalid proof type") {
                     SupportedProvers::SP1 => SP1::process_proof(proof_string, *height),
@@ -178,9 +258,10 @@ pub async fn manual_proof_relay(
                 }
                 .expect("Failed to construct proof params");
 
-            match l1_provider.submit_proof(post_params).await {
+            // steps already done on the contract are skipped, so relaying twice is safe
+            match settle_batch(chain, &l1_provider, post_params, commit_batch_info).await {
                 Ok(_) => {
-                    println!("Proof submission successful ");
+                    println!("Batch settled on {chain}");
                 }
                 Err(e) => {
                     println!("Transaction failed! {e:?}");
@@ -190,37 +271,173 @@ pub async fn manual_proof_relay(
     }
 }
 
-async fn handle_run_command(cfg: Config) -> Result<()> {
+async fn handle_run_command(cfg: Config, config_path: PathBuf) -> Result<()> {
     // Run the main process
-    let _ = run(cfg).await.map_err(|e| {
-        tracing::error!("Error running aggregator: {}", e);
+    let _ = run(cfg, config_path).await.map_err(|e| {
+        tracing::error!("Error running aggregator: {:#}", e);
         process::exit(1);
     });
 
     Ok(())
 }
 
-async fn delete_db(cfg: Config) -> Result<()> {
-    let db = DB::new(cfg.global.threshold, cfg.global.db_path).await;
-    let _ = db
-        .delete_db()
-        .await
-        .map_err(|e| tracing::error!("Failed to delete DB: {:?}", e));
+async fn open_db(cfg: &Config) -> DB {
+    DB::new(
+        cfg.global.threshold,
+        cfg.global.db_path.expose().to_string(),
+        cfg.global.blob_path.clone(),
+    )
+    .await
+}
+
+async fn handle_export_command(cfg: Config, from: u64, to: u64, dir: &Path) -> Result<()> {
+    let db = open_db(&cfg).await;
+    let manifest = export(&db, dir, from, to).await?;
+    for file in &manifest.files {
+        println!(
+            "{}: {} records, checksum {}",
+            file.name, file.records, file.checksum
+        );
+    }
+    println!("Exported to {}", dir.display());
+    Ok(())
+}
+
+async fn handle_import_command(cfg: Config, dir: &Path) -> Result<()> {
+    let db = open_db(&cfg).await;
+    let report = import(&db, dir).await?;
+    println!(
+        "Imported {} blocks, {} proofs and {} settlements, {} proofs were already stored",
+        report.blocks, report.proofs, report.settlements, report.duplicates
+    );
     Ok(())
 }
 
-fn handle_show_command(cfg: Config) -> Result<()> {
+async fn delete_db(
+    cfg: Config,
+    scope: DeleteScope,
+    yes: bool,
+    export_dir: Option<&Path>,
+) -> Result<()> {
+    let name = database_name(cfg.global.db_path.expose());
+    let what = describe_scope(&scope);
+    if !yes && !confirm_name(&name, &what)? {
+        println!("Nothing deleted");
+        process::exit(1);
+    }
+
+    let db = open_db(&cfg).await;
+    if let Some(dir) = export_dir {
+        let from = scope.from.unwrap_or(0);
+        let to = scope.to.unwrap_or(LAST_HEIGHT);
+        export(&db, dir, from, to).await?;
+        println!("Exported to {} before deleting", dir.display());
+    }
+
+    if scope.is_everything() {
+        db.delete_db().await?;
+        println!("Deleted everything in {name}");
+        return Ok(());
+    }
+    let chains: Vec<String> = cfg.l1s.keys().cloned().collect();
+    let report = db.delete_scoped(&scope, &chains).await?;
+    println!(
+        "Deleted {} blocks, {} proof blobs and {} submission log entries in {name}",
+        report.blocks, report.blobs, report.submissions
+    );
+    Ok(())
+}
+
+async fn handle_prune_command(cfg: Config, dry_run: bool) -> Result<()> {
+    let db = open_db(&cfg).await;
+    let chains: Vec<String> = cfg.l1s.keys().cloned().collect();
+    let report = db.prune(&cfg.retention, &chains, dry_run).await?;
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for (height, identifier) in &report.proofs {
+        println!("  height {height}: proof of {identifier}");
+    }
+    println!(
+        "{verb} {} proofs, {} proof blobs and {} rejected submissions",
+        report.proofs.len(),
+        report.blobs,
+        report.submissions
+    );
+    Ok(())
+}
+
+fn describe_scope(scope: &DeleteScope) -> String {
+    let what = match scope.collection {
+        Some(Collection::Blocks) => "blocks",
+        Some(Collection::SubmissionLog) => "the submission log",
+        None if scope.unsettled => "blocks",
+        None => "blocks and the submission log",
+    };
+    let settled = if scope.unsettled {
+        " not settled on every L1"
+    } else {
+        ""
+    };
+    match (scope.from, scope.to) {
+        (None, None) if scope.is_everything() => "everything".to_string(),
+        (None, None) => format!("all {what}{settled}"),
+        (from, to) => format!(
+            "{what}{settled} from height {} to {}",
+            from.unwrap_or(0),
+            to.map_or("the latest".to_string(), |to| to.to_string())
+        ),
+    }
+}
+
+/// Asks for the database name, so a command run against the wrong config stops here
+fn confirm_name(name: &str, what: &str) -> Result<bool> {
+    println!("About to delete {what} in {name}");
+    print!("Type the database name to confirm: ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim() == name)
+}
+
+async fn handle_validate_command(cfg: Config, deep: bool) -> Result<()> {
+    // the config was already validated when it was loaded
+    if !deep {
+        println!("Config ok");
+        return Ok(());
+    }
+
+    let report = deep_validate(&cfg).await;
+    report.print();
+    if !report.passed() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn handle_show_command(cfg: Config, sources: ConfigSources, reveal: bool) -> Result<()> {
+    if reveal {
+        eprintln!("Warning: printing secrets in plain text");
+        reveal_secrets();
+    }
     let pretty_printed = serde_json::to_string_pretty(&cfg).expect("Failed to serialize config");
     println!("{}", pretty_printed);
+
+    println!("Value sources:");
+    let effective = serde_yaml::to_value(&cfg)?;
+    for (path, source) in sources.report(&effective) {
+        println!("  {}: {}", path, source);
+    }
     Ok(())
 }
 
-fn load_and_validate_config(config_path: PathBuf) -> Result<Config> {
+/// Loads the config file with its environment and secret file overrides
+pub fn load_and_validate_config(config_path: PathBuf) -> Result<(Config, ConfigSources)> {
     let config_content = load_config(config_path)?;
 
-    let cfg: Config = serde_yaml::from_str(&config_content)?;
+    let (layered, sources) = load_layered(&config_content)?;
+    let cfg: Config = serde_yaml::from_value(layered)?;
 
     cfg.validate()?;
 
-    Ok(cfg)
+    Ok((cfg, sources))
 }
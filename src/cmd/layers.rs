@@ -0,0 +1,286 @@
+use std::{collections::BTreeMap, env, fmt, fs};
+
+use anyhow::{Context, Error, Result};
+use serde_yaml::{Mapping, Value};
+
+static ENV_PREFIX: &str = "TWARB_";
+
+/// Separates the levels of the config path in an environment variable name,
+/// `TWARB_L1S__ETHEREUM__RPC` sets `l1s.ethereum.rpc`
+static PATH_SEPARATOR: &str = "__";
+
+static FILE_SUFFIX: &str = "_file";
+
+/// Top level keys of the config. Other `TWARB_*` variables, like a keystore `password_env`, are not overrides.
+static CONFIG_SECTIONS: [&str; 6] = ["global", "elf", "l2", "provers", "l1s", "retention"];
+
+/// Secret fields that can be read from a file given as `<field>_file`, in the YAML or the environment
+static SECRET_FIELDS: [&str; 3] = ["private_key", "solana_password", "db_path"];
+
+/// Where an effective config value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Yaml,
+    Env(String),
+    SecretFile(String),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Yaml => write!(f, "config file"),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::SecretFile(path) => write!(f, "secret file {}", path),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Source of each config value, by dotted path
+#[derive(Debug, Default)]
+pub struct ConfigSources(BTreeMap<String, Source>);
+
+impl ConfigSources {
+    /// Source of a value, or of the closest parent that was set as a whole
+    pub fn get(&self, path: &str) -> Source {
+        let mut path = path;
+        loop {
+            if let Some(source) = self.0.get(path) {
+                return source.clone();
+            }
+            match path.rfind('.') {
+                Some(idx) => path = &path[..idx],
+                None => return Source::Default,
+            }
+        }
+    }
+
+    /// Source of every value in the effective config
+    pub fn report(&self, effective: &Value) -> Vec<(String, Source)> {
+        let mut paths = vec![];
+        leaves(effective, "", &mut paths);
+        paths
+            .into_iter()
+            .map(|path| {
+                let source = self.get(&path);
+                (path, source)
+            })
+            .collect()
+    }
+
+    /// Forgets the sources below `path`, when it was replaced as a whole
+    fn replace(&mut self, path: &str, source: Source) {
+        let children = format!("{}.", path);
+        self.0.retain(|p, _| !p.starts_with(&children));
+        self.0.insert(path.to_string(), source);
+    }
+}
+
+/// Reads the config in layers: the YAML file, then `TWARB_<SECTION>__*` environment variables, then `*_file` secrets
+pub fn load_layered(yaml: &str) -> Result<(Value, ConfigSources)> {
+    layer(yaml, env::vars())
+}
+
+fn layer(
+    yaml: &str,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<(Value, ConfigSources)> {
+    let mut root: Value = serde_yaml::from_str(yaml)?;
+    let mut sources = ConfigSources::default();
+
+    let mut paths = vec![];
+    leaves(&root, "", &mut paths);
+    for path in paths {
+        sources.0.insert(path, Source::Yaml);
+    }
+
+    // sorted so that the result does not depend on the order of the environment
+    let mut overrides: Vec<(String, Vec<String>, String)> = vars
+        .filter_map(|(name, value)| {
+            let segments: Vec<String> = name
+                .strip_prefix(ENV_PREFIX)?
+                .split(PATH_SEPARATOR)
+                .map(str::to_lowercase)
+                .collect();
+            CONFIG_SECTIONS
+                .contains(&segments[0].as_str())
+                .then_some((name, segments, value))
+        })
+        .collect();
+    overrides.sort();
+    for (name, segments, value) in overrides {
+        let path = set_path(&mut root, &segments, &value)
+            .with_context(|| format!("Invalid config override {}", name))?;
+        sources.replace(&path, Source::Env(name));
+    }
+
+    resolve_secret_files(&mut root, "", &mut sources)?;
+    Ok((root, sources))
+}
+
+/// Sets the value at the path, creating missing levels. Existing keys match case-insensitively.
+/// Returns the dotted path with the key names used in the config.
+fn set_path(node: &mut Value, segments: &[String], raw: &str) -> Result<String> {
+    let Some((first, rest)) = segments.split_first() else {
+        return Err(Error::msg("empty config path"));
+    };
+    if node.is_null() {
+        *node = Value::Mapping(Mapping::new());
+    }
+
+    let (name, child) = match node {
+        Value::Mapping(map) => {
+            let key = map
+                .keys()
+                .filter_map(Value::as_str)
+                .find(|k| k.eq_ignore_ascii_case(first))
+                .map(str::to_string)
+                .unwrap_or_else(|| first.clone());
+            if !map.contains_key(key.as_str()) {
+                map.insert(Value::String(key.clone()), Value::Null);
+            }
+            let child = map.get_mut(key.as_str()).expect("key was inserted above");
+            (key, child)
+        }
+        Value::Sequence(seq) => {
+            let idx = first
+                .parse::<usize>()
+                .ok()
+                .filter(|idx| *idx < seq.len())
+                .ok_or_else(|| Error::msg(format!("no list entry {}", first)))?;
+            (first.clone(), &mut seq[idx])
+        }
+        _ => return Err(Error::msg(format!("{} is not a section", first))),
+    };
+
+    if rest.is_empty() {
+        *child = parse_override(child, raw);
+        Ok(name)
+    } else {
+        let tail = set_path(child, rest, raw)?;
+        Ok(format!("{}.{}", name, tail))
+    }
+}
+
+/// Strings stay strings. Anything else is read as YAML, so numbers, flags and `[a, b]` lists work.
+fn parse_override(current: &Value, raw: &str) -> Value {
+    if current.is_string() {
+        return Value::String(raw.to_string());
+    }
+    match serde_yaml::from_str::<Value>(raw) {
+        Ok(value) if !value.is_null() => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Replaces every `<secret>_file` key with `<secret>` holding the contents of that file
+fn resolve_secret_files(node: &mut Value, prefix: &str, sources: &mut ConfigSources) -> Result<()> {
+    match node {
+        Value::Mapping(map) => {
+            for field in SECRET_FIELDS {
+                let file_key = format!("{}{}", field, FILE_SUFFIX);
+                let Some(file) = map.remove(file_key.as_str()) else {
+                    continue;
+                };
+                let file = file
+                    .as_str()
+                    .ok_or_else(|| Error::msg(format!("{} must be a file path", file_key)))?
+                    .to_string();
+                let value = fs::read_to_string(&file)
+                    .with_context(|| format!("Error reading {} from {}", field, file))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string();
+                map.insert(Value::String(field.to_string()), Value::String(value));
+
+                sources.0.remove(&join(prefix, &file_key));
+                sources.replace(&join(prefix, field), Source::SecretFile(file));
+            }
+            for (key, child) in map.iter_mut() {
+                if let Some(key) = key.as_str() {
+                    resolve_secret_files(child, &join(prefix, key), sources)?;
+                }
+            }
+        }
+        Value::Sequence(seq) => {
+            for (idx, child) in seq.iter_mut().enumerate() {
+                resolve_secret_files(child, &join(prefix, &idx.to_string()), sources)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn leaves(node: &Value, prefix: &str, paths: &mut Vec<String>) {
+    match node {
+        Value::Mapping(map) if !map.is_empty() => {
+            for (key, child) in map {
+                if let Some(key) = key.as_str() {
+                    leaves(child, &join(prefix, key), paths);
+                }
+            }
+        }
+        Value::Sequence(seq) if !seq.is_empty() => {
+            for (idx, child) in seq.iter().enumerate() {
+                leaves(child, &join(prefix, &idx.to_string()), paths);
+            }
+        }
+        _ => paths.push(prefix.to_string()),
+    }
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{layer, Source};
+
+    #[test]
+    fn test_layered_overrides() {
+        let secret = std::env::temp_dir().join(format!("twarb-private-key-{}", std::process::id()));
+        std::fs::write(&secret, "0xabc\n").unwrap();
+
+        let yaml = "global:\n  threshold: 2\nl1s:\n  Ethereum:\n    rpc: http://a\n    balance_threshold: \"10\"\n";
+        let vars = vec![
+            ("TWARB_GLOBAL__THRESHOLD".to_string(), "3".to_string()),
+            ("TWARB_L1S__ETHEREUM__RPC".to_string(), "ws://b".to_string()),
+            (
+                "TWARB_L1S__ETHEREUM__SIGNER__PRIVATE_KEY_FILE".to_string(),
+                secret.display().to_string(),
+            ),
+            ("OTHER".to_string(), "ignored".to_string()),
+            ("TWARB_ETHEREUM_PASSWORD".to_string(), "ignored".to_string()),
+        ];
+        let (config, sources) = layer(yaml, vars.into_iter()).unwrap();
+
+        assert_eq!(config["global"]["threshold"].as_u64(), Some(3));
+        assert_eq!(config["l1s"]["Ethereum"]["rpc"].as_str(), Some("ws://b"));
+        assert_eq!(
+            config["l1s"]["Ethereum"]["signer"]["private_key"].as_str(),
+            Some("0xabc")
+        );
+        assert!(config["l1s"]["Ethereum"]["signer"]
+            .get("private_key_file")
+            .is_none());
+        assert!(config.get("ethereum_password").is_none());
+
+        assert_eq!(
+            sources.get("l1s.Ethereum.rpc"),
+            Source::Env("TWARB_L1S__ETHEREUM__RPC".to_string())
+        );
+        assert_eq!(
+            sources.get("l1s.Ethereum.signer.private_key"),
+            Source::SecretFile(secret.display().to_string())
+        );
+        assert_eq!(sources.get("l1s.Ethereum.balance_threshold"), Source::Yaml);
+        assert_eq!(sources.get("global.logging"), Source::Default);
+        std::fs::remove_file(secret).unwrap();
+    }
+}
@@ -1,2 +1,4 @@
 pub mod cfg;
+pub mod layers;
 pub mod logger;
+pub mod validate;
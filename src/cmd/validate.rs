@@ -0,0 +1,264 @@
+use std::{collections::HashMap, fmt, fs};
+
+use alloy::network::{Ethereum, NetworkWallet};
+use alloy_primitives::{
+    utils::{format_units, parse_units},
+    Address,
+};
+use alloy_provider::Provider;
+use sp1_sdk::{HashableKey, ProverClient};
+
+use crate::{
+    chains::evm::{endpoints::RpcPool, provider::EVMProviderConfig, signer::make_wallet},
+    config::{Config, EVMConfig, L1Details, L2Details},
+    types::SupportedProvers,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Pass => write!(f, "PASS"),
+            Status::Fail => write!(f, "FAIL"),
+            Status::Skip => write!(f, "SKIP"),
+        }
+    }
+}
+
+pub struct Check {
+    pub item: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Result of every check made by `validate --deep`
+#[derive(Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn add(&mut self, item: String, status: Status, detail: String) {
+        self.checks.push(Check {
+            item,
+            status,
+            detail,
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != Status::Fail)
+    }
+
+    pub fn print(&self) {
+        for check in &self.checks {
+            println!("[{}] {}: {}", check.status, check.item, check.detail);
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|c| c.status == Status::Fail)
+            .count();
+        println!(
+            "{} checks, {} failed. Deep validation {}",
+            self.checks.len(),
+            failed,
+            if failed == 0 { "passed" } else { "failed" }
+        );
+    }
+}
+
+/// Checks the config against the live chains: chain ids and contract code on every endpoint,
+/// the L1 signers and their balances, and the SP1 verifying key
+pub async fn deep_validate(cfg: &Config) -> Report {
+    let mut report = Report::default();
+
+    check_l2(&cfg.l2, &mut report).await;
+
+    let mut l1s: Vec<_> = cfg.l1s.iter().collect();
+    l1s.sort_by_key(|(name, _)| *name);
+    for (name, details) in l1s {
+        match details {
+            L1Details::Solana(_) => report.add(
+                format!("{} checks", name),
+                Status::Skip,
+                "deep checks are not supported for solana".to_string(),
+            ),
+            L1Details::EVM(evmconfig) => check_evm_l1(name, evmconfig, &mut report).await,
+        }
+    }
+
+    check_sp1_vkey(&cfg.elf, &mut report);
+    report
+}
+
+async fn check_l2(l2: &L2Details, report: &mut Report) {
+    let Some(contract) = parse_contract("l2", &l2.messenger_contract, report) else {
+        return;
+    };
+    let config = EVMProviderConfig::new(l2.rpc.clone(), l2.messenger_contract.clone())
+        .with_fallbacks(l2.fallback_rpcs.clone(), l2.read_quorum);
+    let Some(rpcs) = connect("l2", &config, report).await else {
+        return;
+    };
+
+    check_chain_ids("l2", &rpcs, l2.chain_id, report).await;
+    check_bytecode("l2 messenger_contract", &rpcs, contract, report).await;
+}
+
+async fn check_evm_l1(name: &str, evmconfig: &EVMConfig, report: &mut Report) {
+    let Some(contract) = parse_contract(name, &evmconfig.contract, report) else {
+        return;
+    };
+    // connect without the signer, so a bad signer does not hide the endpoint checks
+    let config = EVMProviderConfig::new(evmconfig.rpc.clone(), evmconfig.contract.clone())
+        .with_fallbacks(evmconfig.fallback_rpcs.clone(), evmconfig.read_quorum);
+    let Some(rpcs) = connect(name, &config, report).await else {
+        return;
+    };
+
+    check_chain_ids(name, &rpcs, evmconfig.chain_id, report).await;
+    check_bytecode(&format!("{} contract", name), &rpcs, contract, report).await;
+
+    let address = match make_wallet(&evmconfig.signer) {
+        Ok(wallet) => {
+            let address = NetworkWallet::<Ethereum>::default_signer_address(&wallet);
+            report.add(
+                format!("{} signer", name),
+                Status::Pass,
+                address.to_string(),
+            );
+            address
+        }
+        Err(e) => {
+            report.add(format!("{} signer", name), Status::Fail, e.to_string());
+            return;
+        }
+    };
+
+    let item = format!("{} signer balance", name);
+    let threshold = match parse_units(&evmconfig.balance_threshold, "ether") {
+        Ok(threshold) => threshold.get_absolute(),
+        Err(e) => {
+            report.add(
+                item,
+                Status::Fail,
+                format!("invalid balance_threshold: {}", e),
+            );
+            return;
+        }
+    };
+    match rpcs
+        .failover(|provider| async move { provider.get_balance(address).await })
+        .await
+    {
+        Ok(balance) => {
+            let shown = format_units(balance, "eth").unwrap_or_else(|_| balance.to_string());
+            if balance < threshold {
+                report.add(
+                    item,
+                    Status::Fail,
+                    format!(
+                        "{} eth, below threshold {} eth",
+                        shown, evmconfig.balance_threshold
+                    ),
+                );
+            } else {
+                report.add(item, Status::Pass, format!("{} eth", shown));
+            }
+        }
+        Err(e) => report.add(item, Status::Fail, e.to_string()),
+    }
+}
+
+fn parse_contract(chain: &str, contract: &str, report: &mut Report) -> Option<Address> {
+    match contract.trim().parse::<Address>() {
+        Ok(address) => Some(address),
+        Err(e) => {
+            report.add(
+                format!("{} contract address", chain),
+                Status::Fail,
+                format!("{}: {}", contract, e),
+            );
+            None
+        }
+    }
+}
+
+async fn connect(chain: &str, config: &EVMProviderConfig, report: &mut Report) -> Option<RpcPool> {
+    match config.build().await {
+        Ok(rpcs) => Some(rpcs),
+        Err(e) => {
+            report.add(
+                format!("{} rpc connection", chain),
+                Status::Fail,
+                format!("{:#}", e),
+            );
+            None
+        }
+    }
+}
+
+async fn check_chain_ids(chain: &str, rpcs: &RpcPool, expected: Option<u64>, report: &mut Report) {
+    for endpoint in rpcs.endpoints() {
+        let item = format!("{} chain id ({})", chain, endpoint.url);
+        match endpoint.provider.get_chain_id().await {
+            Ok(id) => match expected {
+                Some(expected) if expected != id => report.add(
+                    item,
+                    Status::Fail,
+                    format!("expected {}, endpoint returned {}", expected, id),
+                ),
+                Some(_) => report.add(item, Status::Pass, id.to_string()),
+                None => report.add(
+                    item,
+                    Status::Pass,
+                    format!("{}, no chain_id in config to compare", id),
+                ),
+            },
+            Err(e) => report.add(item, Status::Fail, format!("unreachable: {}", e)),
+        }
+    }
+}
+
+async fn check_bytecode(item: &str, rpcs: &RpcPool, contract: Address, report: &mut Report) {
+    let item = format!("{} bytecode", item);
+    match rpcs
+        .failover(|provider| async move { provider.get_code_at(contract).await })
+        .await
+    {
+        Ok(code) if !code.is_empty() => report.add(
+            item,
+            Status::Pass,
+            format!("{} bytes at {}", code.len(), contract),
+        ),
+        Ok(_) => report.add(item, Status::Fail, format!("no code at {}", contract)),
+        Err(e) => report.add(item, Status::Fail, e.to_string()),
+    }
+}
+
+fn check_sp1_vkey(elfs: &HashMap<String, String>, report: &mut Report) {
+    let item = "sp1 vkey".to_string();
+    let Some(path) = elfs
+        .get(&SupportedProvers::SP1.to_string())
+        .filter(|path| !path.is_empty())
+    else {
+        report.add(item, Status::Skip, "no sp1 elf configured".to_string());
+        return;
+    };
+
+    match fs::read(path) {
+        Ok(elf) => {
+            let client = ProverClient::new();
+            let (_, vk) = client.setup(&elf);
+            report.add(item, Status::Pass, vk.bytes32());
+        }
+        Err(e) => report.add(item, Status::Fail, format!("{}: {}", path, e)),
+    }
+}
@@ -1,45 +1,67 @@
+use alloy_primitives::{utils::parse_units, Address};
 use anyhow::Error;
 use anyhow::Result;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::database::{blob::is_valid_blob_path, storage::is_valid_db_path};
 use crate::types::SupportedProvers;
 use crate::utils::check_directory_exists;
-use crate::utils::is_valid_url;
+use crate::utils::secret::Secret;
+use crate::utils::{is_valid_rpc_url, is_valid_url};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub global: GlobalConfig,
     pub elf: HashMap<String, String>,
     pub l2: L2Details,
     pub provers: HashMap<String, ProverDetails>,
     pub l1s: HashMap<String, L1Details>,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GlobalConfig {
     pub logging: String,
     pub server_port: u16,
     pub threshold: usize,
-    pub db_path: String,
+    pub db_path: Secret,
+    /// Where proof payloads are kept: `gridfs://`, `file://<dir>` or `memory://`.
+    /// Defaults to GridFS on MongoDB and a directory next to the SQLite file.
+    #[serde(default)]
+    pub blob_path: Option<String>,
     pub balance_check_interval: u64, // in minutes
+    /// First L2 height to be settled on the L1s. Batches are posted in order from here.
+    pub start_height: Option<u64>,
+    /// Seconds to let queued proofs and settlements finish after SIGINT or SIGTERM
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct L2Details {
     pub chain_type: String,
     pub messenger_contract: String,
     pub rpc: String,
+    /// Expected chain id, checked by `validate --deep`
+    pub chain_id: Option<u64>,
+    /// Further L2 endpoints. Every batch is fetched from all of them and must match before it is posted.
+    #[serde(default)]
+    pub fallback_rpcs: Vec<String>,
+    /// Number of L2 endpoints that must return the same batch
+    #[serde(default = "default_read_quorum")]
+    pub read_quorum: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ProverDetails {
     pub prover_ip: String,
     pub prover_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum L1Details {
     Solana(SolanaConfig),
@@ -55,21 +77,142 @@ impl L1Details {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SolanaConfig {
     pub contract: String,
     pub rpc: String,
     pub balance_threshold: String,
     pub solana_key_path: String,
-    pub solana_password: String,
+    pub solana_password: Secret,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct EVMConfig {
     pub contract: String,
     pub balance_threshold: String,
     pub rpc: String,
-    pub private_key: String,
+    /// Expected chain id, checked by `validate --deep`
+    pub chain_id: Option<u64>,
+    /// Tried in order when `rpc` fails
+    #[serde(default)]
+    pub fallback_rpcs: Vec<String>,
+    /// Number of endpoints that must return the same nonce, receipt or contract state
+    #[serde(default = "default_read_quorum")]
+    pub read_quorum: usize,
+    pub signer: SignerConfig,
+    #[serde(default)]
+    pub gas: GasConfig,
+    #[serde(default)]
+    pub finality: FinalityConfig,
+}
+
+/// Where the key that signs settlement transactions comes from
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SignerConfig {
+    /// Encrypted JSON keystore, unlocked with the contents of `password_file` or the `password_env` variable
+    Keystore {
+        path: String,
+        password_file: Option<String>,
+        password_env: Option<String>,
+    },
+    /// Remote signer service answering `eth_signTransaction` for `address`
+    Remote { url: String, address: String },
+    /// Plaintext key in the config file. Development only, refused unless `allow_raw_key` is set.
+    Raw {
+        private_key: Secret,
+        #[serde(default)]
+        allow_raw_key: bool,
+    },
+}
+
+/// When a settlement transaction on an L1 is considered final
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FinalityConfig {
+    /// Blocks on top of the transaction block. Ignored when `tag` is set.
+    #[serde(default)]
+    pub confirmations: u64,
+    /// `safe` or `finalized`, to follow the chain's own finality instead of a fixed depth
+    pub tag: Option<String>,
+}
+
+/// How long data is kept once its height is final on every L1. The proof that was posted for a
+/// height is always kept. Unset durations keep data forever.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RetentionConfig {
+    /// Days to keep the proofs of other provers
+    pub extra_proofs_days: Option<u64>,
+    /// Days to keep submission log entries that were not accepted, whatever their height
+    pub rejected_submissions_days: Option<u64>,
+    /// Minutes between pruner runs
+    #[serde(default = "default_prune_interval")]
+    pub prune_interval: u64,
+}
+
+/// Fee settings for transactions sent to an EVM L1
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GasConfig {
+    /// Use `gasPrice` instead of EIP-1559 fees, for chains without 1559
+    #[serde(default)]
+    pub legacy: bool,
+    /// Ceiling for max fee per gas (gas price in legacy mode), in gwei
+    pub max_fee_per_gas: Option<String>,
+    /// Ceiling for max priority fee per gas, in gwei
+    pub max_priority_fee_per_gas: Option<String>,
+    /// Fee increase in percent when a stuck transaction is replaced
+    #[serde(default = "default_bump_percent")]
+    pub bump_percent: u64,
+    /// Seconds to wait for a receipt before the transaction is replaced
+    #[serde(default = "default_resend_interval")]
+    pub resend_interval: u64,
+}
+
+fn default_shutdown_timeout() -> u64 {
+    60
+}
+
+fn default_prune_interval() -> u64 {
+    60
+}
+
+fn default_read_quorum() -> usize {
+    1
+}
+
+fn default_bump_percent() -> u64 {
+    15
+}
+
+fn default_resend_interval() -> u64 {
+    15
+}
+
+/// Parses a gas fee ceiling given in gwei to wei
+pub fn parse_gwei(gwei: &str) -> Result<u128> {
+    let wei = parse_units(gwei, "gwei")?.get_absolute();
+    Ok(u128::try_from(wei)?)
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            extra_proofs_days: None,
+            rejected_submissions_days: None,
+            prune_interval: default_prune_interval(),
+        }
+    }
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            legacy: false,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            bump_percent: default_bump_percent(),
+            resend_interval: default_resend_interval(),
+        }
+    }
 }
 
 impl Config {
@@ -86,6 +229,27 @@ impl Config {
             )));
         }
 
+        // The scheme of db_path picks the storage backend
+        if !is_valid_db_path(self.global.db_path.expose()) {
+            return Err(Error::msg(
+                "db_path must start with mongodb://, mongodb+srv://, sqlite:// or memory://",
+            ));
+        }
+
+        if let Some(blob_path) = &self.global.blob_path {
+            if !is_valid_blob_path(blob_path) {
+                return Err(Error::msg(
+                    "blob_path must start with gridfs://, file:// or memory://",
+                ));
+            }
+        }
+
+        if self.retention.prune_interval == 0 {
+            return Err(Error::msg(
+                "retention.prune_interval must be greater than 0",
+            ));
+        }
+
         // Ensure ELF File exists
         for v in self.elf.values() {
             if !v.is_empty() && !check_directory_exists(v) {
@@ -95,9 +259,27 @@ impl Config {
             }
         }
 
-        if !is_valid_url(&self.l2.rpc) {
+        if !is_valid_rpc_url(&self.l2.rpc) {
             return Err(Error::msg("Invalid l2_rpc URL".to_string()));
         }
+        for rpc in &self.l2.fallback_rpcs {
+            if !is_valid_rpc_url(rpc) {
+                return Err(Error::msg(format!("Invalid l2 fallback rpc URL: {}", rpc)));
+            }
+        }
+        let l2_endpoints = self.l2.fallback_rpcs.len() + 1;
+        if self.l2.read_quorum < 1 || self.l2.read_quorum > l2_endpoints {
+            return Err(Error::msg(format!(
+                "l2 read_quorum must be between 1 and the number of rpc endpoints ({}), found: {}",
+                l2_endpoints, self.l2.read_quorum
+            )));
+        }
+        if self.l2.messenger_contract.parse::<Address>().is_err() {
+            return Err(Error::msg(format!(
+                "Invalid l2 messenger_contract address: {}",
+                self.l2.messenger_contract
+            )));
+        }
 
         for value in self.provers.values() {
             if !is_valid_url(&value.prover_ip) {
@@ -121,16 +303,117 @@ impl Config {
                             key, solana_config.rpc
                         )));
                     }
+                    // settling to solana is not implemented yet
+                    return Err(Error::msg(format!(
+                        "Solana L1s are not supported yet: {}",
+                        key
+                    )));
                 }
                 L1Details::EVM(evmconfig) => {
                     // required evm validation
 
-                    if !is_valid_url(&evmconfig.rpc) {
+                    if !is_valid_rpc_url(&evmconfig.rpc) {
                         return Err(Error::msg(format!(
                             "Invalid l1_rpc URL for {}: {}",
                             key, evmconfig.rpc
                         )));
                     }
+                    if evmconfig.contract.parse::<Address>().is_err() {
+                        return Err(Error::msg(format!(
+                            "Invalid contract address for {}: {}",
+                            key, evmconfig.contract
+                        )));
+                    }
+
+                    for rpc in &evmconfig.fallback_rpcs {
+                        if !is_valid_rpc_url(rpc) {
+                            return Err(Error::msg(format!(
+                                "Invalid fallback rpc URL for {}: {}",
+                                key, rpc
+                            )));
+                        }
+                    }
+                    let endpoints = evmconfig.fallback_rpcs.len() + 1;
+                    if evmconfig.read_quorum < 1 || evmconfig.read_quorum > endpoints {
+                        return Err(Error::msg(format!(
+                            "read_quorum for {} must be between 1 and the number of rpc endpoints ({}), found: {}",
+                            key, endpoints, evmconfig.read_quorum
+                        )));
+                    }
+
+                    match &evmconfig.signer {
+                        SignerConfig::Keystore {
+                            path,
+                            password_file,
+                            password_env,
+                        } => {
+                            if !check_directory_exists(path) {
+                                return Err(Error::msg(format!(
+                                    "keystore for {} does not exist: {}",
+                                    key, path
+                                )));
+                            }
+                            if password_file.is_none() && password_env.is_none() {
+                                return Err(Error::msg(format!(
+                                    "keystore for {} needs a password_file or password_env",
+                                    key
+                                )));
+                            }
+                        }
+                        SignerConfig::Remote { url, address } => {
+                            if !is_valid_url(url) {
+                                return Err(Error::msg(format!(
+                                    "Invalid remote signer URL for {}: {}",
+                                    key, url
+                                )));
+                            }
+                            if address.parse::<Address>().is_err() {
+                                return Err(Error::msg(format!(
+                                    "Invalid remote signer address for {}: {}",
+                                    key, address
+                                )));
+                            }
+                        }
+                        SignerConfig::Raw { allow_raw_key, .. } => {
+                            if !allow_raw_key {
+                                return Err(Error::msg(format!(
+                                    "raw private key for {} needs allow_raw_key: true, use a keystore or remote signer instead",
+                                    key
+                                )));
+                            }
+                        }
+                    }
+
+                    if let Some(tag) = &evmconfig.finality.tag {
+                        if tag != "safe" && tag != "finalized" {
+                            return Err(Error::msg(format!(
+                                "finality tag for {} must be safe or finalized, found: {}",
+                                key, tag
+                            )));
+                        }
+                    }
+
+                    // replacement transactions need at least a 10% fee increase
+                    if evmconfig.gas.bump_percent < 10 {
+                        return Err(Error::msg(format!(
+                            "gas bump_percent for {} must be at least 10",
+                            key
+                        )));
+                    }
+                    for ceiling in [
+                        &evmconfig.gas.max_fee_per_gas,
+                        &evmconfig.gas.max_priority_fee_per_gas,
+                    ]
+                    .into_iter()
+                    .flatten()
+                    {
+                        if parse_gwei(ceiling).is_err() {
+                            return Err(Error::msg(format!(
+                                "Invalid gas fee ceiling for {}: {}",
+                                key, ceiling
+                            )));
+                        }
+                    }
                 }
             }
         }
@@ -138,3 +421,25 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Config, L1Details, SignerConfig};
+
+    #[test]
+    fn test_sample_config_parses() {
+        let cfg: Config = serde_yaml::from_str(include_str!("../config.yaml")).unwrap();
+        for details in cfg.l1s.values() {
+            let L1Details::EVM(evm) = details else {
+                continue;
+            };
+            assert!(matches!(
+                evm.signer,
+                SignerConfig::Raw {
+                    allow_raw_key: true,
+                    ..
+                }
+            ));
+        }
+    }
+}
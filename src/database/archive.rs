@@ -0,0 +1,309 @@
+use std::{collections::HashSet, path::Path};
+
+use alloy_primitives::Keccak256;
+use anyhow::{Error, Result};
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+};
+
+use super::{
+    blob::Blobs,
+    db::DB,
+    schema::{ProverDetails, Settlement},
+    storage::{ProofInsert, Storage},
+};
+
+/// Bumped whenever a record changes in a way older readers cannot follow
+pub static ARCHIVE_VERSION: u32 = 1;
+/// Highest height every backend can store, the default end of an export
+pub static LAST_HEIGHT: u64 = i64::MAX as u64;
+static MANIFEST_FILE: &str = "manifest.json";
+static BLOCKS_FILE: &str = "blocks.jsonl";
+
+/// Describes an export. Written last, so a directory without one is an incomplete export.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub version: u32,
+    pub created: DateTime,
+    pub from: u64,
+    pub to: u64,
+    pub files: Vec<ArchiveFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchiveFile {
+    pub name: String,
+    /// keccak256 of the file contents
+    pub checksum: String,
+    pub records: u64,
+}
+
+/// One line of `blocks.jsonl`: everything kept about a height
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockArchive {
+    pub height: u64,
+    pub threshold_verified: bool,
+    pub proofs: Vec<ArchivedProof>,
+    pub settlements: Vec<Settlement>,
+}
+
+/// A prover's proof with its payload, independent of the blob store it was kept in
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedProof {
+    pub identifier: String,
+    pub proof_type: String,
+    pub verified: bool,
+    pub timestamp: DateTime,
+    pub proof: String,
+}
+
+/// What an import added. Proofs a prover already has at the height are skipped.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub blocks: u64,
+    pub proofs: u64,
+    pub duplicates: u64,
+    pub settlements: u64,
+}
+
+/// Writes the proofs, threshold state and settlements of heights `from..=to` to `dir`
+pub async fn export(db: &DB, dir: &Path, from: u64, to: u64) -> Result<Manifest> {
+    if tokio::fs::try_exists(dir.join(MANIFEST_FILE)).await? {
+        return Err(Error::msg(format!(
+            "An export already exists in {}",
+            dir.display()
+        )));
+    }
+    tokio::fs::create_dir_all(dir).await?;
+
+    let verified: HashSet<u64> = db
+        .store
+        .threshold_verified_blocks()
+        .await?
+        .into_iter()
+        .collect();
+    let mut writer = BufWriter::new(File::create(dir.join(BLOCKS_FILE)).await?);
+    let mut hasher = Keccak256::new();
+    let mut records = 0;
+    for height in db.store.heights(from, to).await? {
+        let mut proofs = vec![];
+        for (identifier, details) in db.store.proofs(height).await? {
+            proofs.push(ArchivedProof {
+                proof: payload(db, &details).await?,
+                identifier,
+                proof_type: details.proof_type,
+                verified: details.verified,
+                timestamp: details.timestamp,
+            });
+        }
+        let record = BlockArchive {
+            height,
+            threshold_verified: verified.contains(&height),
+            proofs,
+            settlements: db.store.settlements(height).await?,
+        };
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        hasher.update(&line);
+        writer.write_all(&line).await?;
+        records += 1;
+    }
+    writer.flush().await?;
+
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        created: DateTime::now(),
+        from,
+        to,
+        files: vec![ArchiveFile {
+            name: BLOCKS_FILE.to_string(),
+            checksum: hasher.finalize().to_string(),
+            records,
+        }],
+    };
+    tokio::fs::write(
+        dir.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )
+    .await?;
+    tracing::info!(
+        "Export written. path:{} from:{} to:{} blocks:{}",
+        dir.display(),
+        from,
+        to,
+        records
+    );
+    Ok(manifest)
+}
+
+/// Restores an export into the database, whatever backend it was taken from. The archive is
+/// checked against its manifest before anything is written. Submission logs are not archived.
+pub async fn import(db: &DB, dir: &Path) -> Result<ImportReport> {
+    let manifest: Manifest =
+        serde_json::from_slice(&tokio::fs::read(dir.join(MANIFEST_FILE)).await?)?;
+    if manifest.version != ARCHIVE_VERSION {
+        return Err(Error::msg(format!(
+            "Unsupported archive version: {}, expected {}",
+            manifest.version, ARCHIVE_VERSION
+        )));
+    }
+    if !manifest.files.iter().any(|file| file.name == BLOCKS_FILE) {
+        return Err(Error::msg(format!(
+            "{} is not in the manifest",
+            BLOCKS_FILE
+        )));
+    }
+    for file in &manifest.files {
+        // names come from the archive, they must not point outside of it
+        if file.name.is_empty() || file.name.contains(['/', '\\']) || file.name.contains("..") {
+            return Err(Error::msg(format!(
+                "Invalid file name in manifest: {}",
+                file.name
+            )));
+        }
+        let checksum = checksum(&dir.join(&file.name)).await?;
+        if checksum != file.checksum {
+            return Err(Error::msg(format!(
+                "Checksum mismatch in {}: expected {} found {}",
+                file.name, file.checksum, checksum
+            )));
+        }
+    }
+
+    let mut report = ImportReport::default();
+    let mut lines = BufReader::new(File::open(dir.join(BLOCKS_FILE)).await?).lines();
+    while let Some(line) = lines.next_line().await? {
+        let record: BlockArchive = serde_json::from_str(&line)?;
+        for proof in record.proofs {
+            let details = ProverDetails {
+                proof: String::new(),
+                proof_hash: Some(Blobs::hash(proof.proof.as_bytes())),
+                proof_type: proof.proof_type,
+                verified: proof.verified,
+                timestamp: proof.timestamp,
+            };
+            match db
+                .store
+                .add_proof(record.height, &proof.identifier, details)
+                .await?
+            {
+                ProofInsert::Added { .. } => {
+                    db.blobs.store(proof.proof.as_bytes()).await?;
+                    report.proofs += 1;
+                }
+                ProofInsert::Duplicate => report.duplicates += 1,
+            }
+        }
+        for settlement in record.settlements {
+            db.store
+                .restore_settlement(record.height, settlement)
+                .await?;
+            report.settlements += 1;
+        }
+        // after the settlements, which create the block when it has no proofs
+        if record.threshold_verified {
+            db.store.claim_threshold(record.height).await?;
+        }
+        report.blocks += 1;
+    }
+    tracing::info!(
+        "Export restored. path:{} blocks:{} proofs:{} duplicates:{} settlements:{}",
+        dir.display(),
+        report.blocks,
+        report.proofs,
+        report.duplicates,
+        report.settlements
+    );
+    Ok(report)
+}
+
+/// The proof payload, from the blob store or from the record for proofs stored inline
+async fn payload(db: &DB, details: &ProverDetails) -> Result<String> {
+    match &details.proof_hash {
+        Some(hash) => Ok(String::from_utf8(db.blobs.load(hash).await?)?),
+        None => Ok(details.proof.clone()),
+    }
+}
+
+async fn checksum(path: &Path) -> Result<String> {
+    let mut hasher = Keccak256::new();
+    let mut reader = BufReader::new(File::open(path).await?);
+    loop {
+        let chunk = reader.fill_buf().await?;
+        if chunk.is_empty() {
+            break;
+        }
+        hasher.update(chunk);
+        let len = chunk.len();
+        reader.consume(len);
+    }
+    Ok(hasher.finalize().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export, import, ArchiveFile};
+    use crate::{
+        database::{db::DB, storage::Storage},
+        types::SupportedProvers,
+    };
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let dir = std::env::temp_dir().join(format!("twarb-export-{}", std::process::id()));
+        let source = DB::new(2, "memory://".to_string(), None).await;
+        let (poster_tx, _poster_rx) = mpsc::channel(10);
+        for prover in ["first", "second"] {
+            source
+                .save_proof_to_db(
+                    prover.to_string(),
+                    SupportedProvers::Dummy,
+                    7,
+                    hex::encode([0, 7]),
+                    poster_tx.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        source.store.set_post_status("bnb", 7, true).await.unwrap();
+
+        let manifest = export(&source, &dir, 0, 10).await.unwrap();
+        assert_eq!(manifest.files[0].records, 1);
+        assert!(export(&source, &dir, 0, 10).await.is_err());
+
+        let target = DB::new(2, "memory://".to_string(), None).await;
+        let report = import(&target, &dir).await.unwrap();
+        assert_eq!((report.proofs, report.settlements), (2, 1));
+        assert_eq!(target.store.threshold_verified_blocks().await.unwrap(), [7]);
+        assert!(target.store.settlements(7).await.unwrap()[0].posted);
+        assert_eq!(
+            target.oldest_proof(7).await.unwrap().proof,
+            hex::encode([0, 7])
+        );
+
+        std::fs::write(dir.join("blocks.jsonl"), "{}\n").unwrap();
+        assert!(import(&target, &dir).await.is_err());
+
+        let mut escaping = manifest;
+        escaping.files.insert(
+            0,
+            ArchiveFile {
+                name: "../blocks.jsonl".to_string(),
+                checksum: String::new(),
+                records: 0,
+            },
+        );
+        std::fs::write(
+            dir.join("manifest.json"),
+            serde_json::to_vec(&escaping).unwrap(),
+        )
+        .unwrap();
+        assert!(import(&target, &dir).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
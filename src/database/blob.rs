@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use alloy_primitives::keccak256;
+use anyhow::{Error, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use futures::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    stream::TryStreamExt,
+};
+use mongodb::{bson::doc, gridfs::GridFsBucket};
+
+use super::storage::Store;
+
+static GRIDFS_SCHEME: &str = "gridfs://";
+static FILE_SCHEME: &str = "file://";
+static MEMORY_SCHEME: &str = "memory://";
+static BLOB_EXTENSION: &str = "zz";
+
+/// Raw storage of compressed proof payloads, keyed by the hash of the uncompressed payload
+pub trait BlobStore {
+    /// Does nothing if a blob with the hash is already stored
+    fn put(&self, hash: &str, compressed: Vec<u8>) -> impl Future<Output = Result<()>> + Send;
+
+    fn get(&self, hash: &str) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send;
+
+    fn delete(&self, hash: &str) -> impl Future<Output = Result<()>> + Send;
+
+    fn delete_all(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Proof payloads, zlib compressed and content addressed, so a payload submitted twice is kept once
+/// and block records only hold its hash
+pub enum Blobs {
+    GridFs(GridFsBlobs),
+    Local(LocalBlobs),
+    Memory(MemoryBlobs),
+}
+
+impl Blobs {
+    /// Opens the store named by `blob_path`: `gridfs://`, `file://<dir>` or `memory://`.
+    /// Without one, proofs go to GridFS on MongoDB, next to the file on SQLite and stay in memory
+    /// for in-memory storage.
+    pub async fn open(blob_path: Option<&str>, store: &Store) -> Result<Blobs> {
+        let Some(blob_path) = blob_path else {
+            return Ok(match store {
+                Store::Mongo(mongo) => Blobs::GridFs(GridFsBlobs::new(mongo.gridfs())),
+                Store::Sqlite(sqlite) => match sqlite.blob_dir() {
+                    Some(dir) => Blobs::Local(LocalBlobs::open(&dir).await?),
+                    None => Blobs::Memory(MemoryBlobs::default()),
+                },
+                Store::Memory(_) => Blobs::Memory(MemoryBlobs::default()),
+            });
+        };
+
+        if blob_path.starts_with(GRIDFS_SCHEME) {
+            let Store::Mongo(mongo) = store else {
+                return Err(Error::msg("gridfs:// blob storage needs a MongoDB db_path"));
+            };
+            return Ok(Blobs::GridFs(GridFsBlobs::new(mongo.gridfs())));
+        }
+        if let Some(dir) = blob_path.strip_prefix(FILE_SCHEME) {
+            return Ok(Blobs::Local(LocalBlobs::open(Path::new(dir)).await?));
+        }
+        if blob_path.starts_with(MEMORY_SCHEME) {
+            return Ok(Blobs::Memory(MemoryBlobs::default()));
+        }
+        Err(Error::msg(
+            "blob_path must start with gridfs://, file:// or memory://",
+        ))
+    }
+
+    /// Key of the payload in the store
+    pub fn hash(payload: &[u8]) -> String {
+        keccak256(payload).to_string()
+    }
+
+    /// Compresses and stores the payload, returns its hash
+    pub async fn store(&self, payload: &[u8]) -> Result<String> {
+        let hash = Blobs::hash(payload);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload)?;
+        self.put(&hash, encoder.finish()?).await?;
+        Ok(hash)
+    }
+
+    /// Reads and decompresses the payload, checking it against its hash
+    pub async fn load(&self, hash: &str) -> Result<Vec<u8>> {
+        let compressed = self
+            .get(hash)
+            .await?
+            .ok_or_else(|| Error::msg(format!("Proof blob not found: {}", hash)))?;
+        let mut payload = vec![];
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut payload)?;
+        if keccak256(&payload).to_string() != hash {
+            return Err(Error::msg(format!("Proof blob corrupted: {}", hash)));
+        }
+        Ok(payload)
+    }
+}
+
+/// Whether `blob_path` names a supported blob store
+pub fn is_valid_blob_path(blob_path: &str) -> bool {
+    [GRIDFS_SCHEME, FILE_SCHEME, MEMORY_SCHEME]
+        .iter()
+        .any(|scheme| blob_path.starts_with(scheme))
+}
+
+impl BlobStore for Blobs {
+    async fn put(&self, hash: &str, compressed: Vec<u8>) -> Result<()> {
+        match self {
+            Blobs::GridFs(gridfs) => gridfs.put(hash, compressed).await,
+            Blobs::Local(local) => local.put(hash, compressed).await,
+            Blobs::Memory(memory) => memory.put(hash, compressed).await,
+        }
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Blobs::GridFs(gridfs) => gridfs.get(hash).await,
+            Blobs::Local(local) => local.get(hash).await,
+            Blobs::Memory(memory) => memory.get(hash).await,
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> Result<()> {
+        match self {
+            Blobs::GridFs(gridfs) => gridfs.delete(hash).await,
+            Blobs::Local(local) => local.delete(hash).await,
+            Blobs::Memory(memory) => memory.delete(hash).await,
+        }
+    }
+
+    async fn delete_all(&self) -> Result<()> {
+        match self {
+            Blobs::GridFs(gridfs) => gridfs.delete_all().await,
+            Blobs::Local(local) => local.delete_all().await,
+            Blobs::Memory(memory) => memory.delete_all().await,
+        }
+    }
+}
+
+/// Blobs in the GridFS bucket of the aggregator database, one file per hash
+pub struct GridFsBlobs {
+    bucket: GridFsBucket,
+}
+
+impl GridFsBlobs {
+    pub fn new(bucket: GridFsBucket) -> Self {
+        Self { bucket }
+    }
+
+    async fn file_ids(&self, hash: &str) -> Result<Vec<mongodb::bson::Bson>> {
+        Ok(self
+            .bucket
+            .find(doc! { "filename": hash })
+            .await?
+            .map_ok(|file| file.id)
+            .try_collect()
+            .await?)
+    }
+}
+
+impl BlobStore for GridFsBlobs {
+    async fn put(&self, hash: &str, compressed: Vec<u8>) -> Result<()> {
+        if !self.file_ids(hash).await?.is_empty() {
+            return Ok(());
+        }
+        let mut upload = self.bucket.open_upload_stream(hash).await?;
+        upload.write_all(&compressed).await?;
+        upload.close().await?;
+        Ok(())
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        if self.file_ids(hash).await?.is_empty() {
+            return Ok(None);
+        }
+        let mut download = self.bucket.open_download_stream_by_name(hash).await?;
+        let mut compressed = vec![];
+        download.read_to_end(&mut compressed).await?;
+        Ok(Some(compressed))
+    }
+
+    async fn delete(&self, hash: &str) -> Result<()> {
+        for id in self.file_ids(hash).await? {
+            self.bucket.delete(id).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops the files and chunks collections of the bucket
+    async fn delete_all(&self) -> Result<()> {
+        self.bucket.drop().await?;
+        Ok(())
+    }
+}
+
+/// Blobs as files in a local directory, named by their hash
+pub struct LocalBlobs {
+    dir: PathBuf,
+}
+
+impl LocalBlobs {
+    pub async fn open(dir: &Path) -> Result<Self> {
+        tokio::fs::create_dir_all(dir).await?;
+        tracing::info!("Proof blobs stored in directory. path:{}", dir.display());
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", hash, BLOB_EXTENSION))
+    }
+}
+
+impl BlobStore for LocalBlobs {
+    /// Written to a temporary file first, so a crash never leaves a partial blob under its hash
+    async fn put(&self, hash: &str, compressed: Vec<u8>) -> Result<()> {
+        let path = self.path(hash);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, compressed).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(hash)).await {
+            Ok(compressed) => Ok(Some(compressed)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(hash)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes every blob, the directory is kept
+    async fn delete_all(&self) -> Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == BLOB_EXTENSION)
+            {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryBlobs {
+    blobs: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl BlobStore for MemoryBlobs {
+    async fn put(&self, hash: &str, compressed: Vec<u8>) -> Result<()> {
+        self.blobs
+            .lock()
+            .unwrap()
+            .entry(hash.to_string())
+            .or_insert(compressed);
+        Ok(())
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.blobs.lock().unwrap().get(hash).cloned())
+    }
+
+    async fn delete(&self, hash: &str) -> Result<()> {
+        self.blobs.lock().unwrap().remove(hash);
+        Ok(())
+    }
+
+    async fn delete_all(&self) -> Result<()> {
+        self.blobs.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BlobStore, Blobs, LocalBlobs};
+
+    #[tokio::test]
+    async fn test_local_blobs_round_trip() {
+        let dir = std::env::temp_dir().join(format!("twarb-blobs-{}", std::process::id()));
+        let blobs = Blobs::Local(LocalBlobs::open(&dir).await.unwrap());
+
+        let payload = "{\"proof\":\"".repeat(1000);
+        let hash = blobs.store(payload.as_bytes()).await.unwrap();
+        assert_eq!(blobs.store(payload.as_bytes()).await.unwrap(), hash);
+        assert!(blobs.get(&hash).await.unwrap().unwrap().len() < payload.len());
+        assert_eq!(blobs.load(&hash).await.unwrap(), payload.as_bytes());
+
+        blobs.delete(&hash).await.unwrap();
+        assert!(blobs.load(&hash).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
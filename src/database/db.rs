@@ -1,195 +1,466 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 
 use anyhow::{Error, Result};
-use futures::stream::StreamExt;
-use mongodb::{
-    bson::{self, doc, Bson, DateTime},
-    Client, Collection, Database,
+use mongodb::bson::DateTime;
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::sleep,
 };
-use tokio::sync::mpsc::{Receiver, Sender};
 use tracing::info_span;
 
 use crate::verifier::verifier::ProofTraits;
 use crate::{
-    poster::poster::PostStatus,
+    chains::chains::LandedTx,
+    config::RetentionConfig,
+    poster::poster::{PostStatus, PostUpdate, SettlementAttempt},
     types::{DummyParams, PostParams, SupportedProvers},
 };
 use crate::{verifier::sp1::SP1, MAX_RETRIES};
 
-use super::schema::{BlockFields, L1Details, ProofDetails, ProverDetails};
+use super::{
+    archive::LAST_HEIGHT,
+    blob::{BlobStore, Blobs},
+    schema::{ProverDetails, SettlementTx, SubmissionLog},
+    storage::{ProofInsert, Storage, Store},
+};
+
+/// First wait before the proof of a newly claimed block is read again, doubled on every retry
+static READ_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What a retention run removed, or would remove on a dry run
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    /// Height and prover of every pruned proof
+    pub proofs: Vec<(u64, String)>,
+    pub blobs: usize,
+    pub submissions: u64,
+}
+
+/// The time `days` days ago, the earliest representable time for spans that do not fit
+fn days_ago(days: u64) -> DateTime {
+    let millis = i64::try_from(days)
+        .ok()
+        .and_then(|days| days.checked_mul(24 * 60 * 60 * 1000))
+        .unwrap_or(i64::MAX);
+    DateTime::from_millis(DateTime::now().timestamp_millis().saturating_sub(millis))
+}
+
+/// Part of the database a scoped delete is limited to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Collection {
+    /// Proofs, threshold state and settlements, with the proof blobs
+    Blocks,
+    SubmissionLog,
+}
+
+/// What `DB::delete_scoped` removes. The default scope is everything.
+#[derive(Debug, Default, Clone)]
+pub struct DeleteScope {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub collection: Option<Collection>,
+    /// Only heights that are not settled on every L1. The submission log is kept.
+    pub unsettled: bool,
+}
+
+impl DeleteScope {
+    pub fn is_everything(&self) -> bool {
+        self.range().is_none() && self.collection.is_none() && !self.unsettled
+    }
+
+    fn range(&self) -> Option<(u64, u64)> {
+        if self.from.is_none() && self.to.is_none() {
+            return None;
+        }
+        Some((self.from.unwrap_or(0), self.to.unwrap_or(LAST_HEIGHT)))
+    }
+}
 
-static DB_NAME: &str = "twine_aggregator";
-static PROOF_COLLECTION_NAME: &str = "proof_collection";
-static POSTER_COLLECTION_NAME: &str = "l1s_collection";
+/// What a scoped delete removed
+#[derive(Debug, Default)]
+pub struct DeleteReport {
+    pub blocks: u64,
+    pub blobs: usize,
+    pub submissions: u64,
+}
 
-async fn connect_to_mongodb(uri: &str) -> mongodb::error::Result<Database> {
-    let client = Client::with_uri_str(uri).await?;
-    let database = client.database(DB_NAME);
-    Ok(database)
+/// Whether the block is marked posted on every chain
+fn settled_on(statuses: Option<&HashMap<String, bool>>, chains: &[String]) -> bool {
+    statuses.is_some_and(|statuses| {
+        chains
+            .iter()
+            .all(|chain| statuses.get(chain) == Some(&true))
+    })
 }
 
 pub struct DB {
-    pub threshold: usize,
-    pub proof_collection: Collection<ProofDetails>,
-    pub l1_collection: Collection<L1Details>,
+    /// Number of provers that must agree on a block. Can change when the config is reloaded.
+    threshold: AtomicUsize,
+    pub store: Store,
+    /// Proof payloads, the store only keeps their hashes
+    pub blobs: Blobs,
 }
 
 impl DB {
-    pub async fn new(threshold: usize, db_conn_str: String) -> Self {
-        let database = match connect_to_mongodb(&db_conn_str).await {
-            Ok(db) => db,
+    /// Connects to the backend named by the scheme of `db_conn_str`, with proofs in the blob store
+    /// named by `blob_path` or the default one for the backend
+    pub async fn new(threshold: usize, db_conn_str: String, blob_path: Option<String>) -> Self {
+        let opened = match Store::open(&db_conn_str).await {
+            Ok(store) => Blobs::open(blob_path.as_deref(), &store)
+                .await
+                .map(|blobs| (store, blobs)),
+            Err(e) => Err(e),
+        };
+        let (store, blobs) = match opened {
+            Ok(opened) => opened,
             Err(e) => {
                 eprintln!("Error: {}", e);
                 panic!("Failed to connect to database");
             }
         };
 
-        let proof_collection: Collection<ProofDetails> = database.collection(PROOF_COLLECTION_NAME);
-        let l1_collection: Collection<L1Details> = database.collection(POSTER_COLLECTION_NAME);
-
         Self {
-            threshold,
-            proof_collection,
-            l1_collection,
+            threshold: AtomicUsize::new(threshold),
+            store,
+            blobs,
         }
     }
 
-    /// Delete all mongodb collections
+    pub fn threshold(&self) -> usize {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    pub fn set_threshold(&self, threshold: usize) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// Delete everything the aggregator stored
     pub async fn delete_db(&self) -> Result<()> {
-        self.proof_collection.drop().await?;
-        self.l1_collection.drop().await?;
-        Ok(())
+        self.store.delete_all().await?;
+        self.blobs.delete_all().await
+    }
+
+    /// Deletes the part of the database in `scope`. `chains` decides which heights are settled.
+    pub async fn delete_scoped(
+        &self,
+        scope: &DeleteScope,
+        chains: &[String],
+    ) -> Result<DeleteReport> {
+        let mut report = DeleteReport::default();
+        let (from, to) = scope.range().unwrap_or((0, LAST_HEIGHT));
+
+        if scope.collection != Some(Collection::SubmissionLog) {
+            let mut heights = self.store.heights(from, to).await?;
+            if scope.unsettled {
+                let posted = self.store.post_statuses().await?;
+                heights.retain(|height| !settled_on(posted.get(height), chains));
+            }
+            let mut hashes = vec![];
+            for height in &heights {
+                for (_, details) in self.store.proofs(*height).await? {
+                    hashes.extend(details.proof_hash);
+                }
+            }
+            hashes.sort_unstable();
+            hashes.dedup();
+
+            // blobs go last, an interrupted delete leaves unused blobs rather than missing ones
+            report.blocks = self.store.delete_blocks(&heights).await?;
+            for hash in &hashes {
+                self.blobs.delete(hash).await?;
+            }
+            report.blobs = hashes.len();
+        }
+
+        if scope.collection != Some(Collection::Blocks) && !scope.unsettled {
+            report.submissions = self.store.delete_submissions(scope.range()).await?;
+        }
+
+        tracing::info!(
+            "Scoped delete done. from:{} to:{} collection:{:?} unsettled:{} blocks:{} blobs:{} submissions:{}",
+            from,
+            to,
+            scope.collection,
+            scope.unsettled,
+            report.blocks,
+            report.blobs,
+            report.submissions
+        );
+        Ok(report)
+    }
+
+    /// The first proof that was submitted for the block, read through from the blob store
+    pub async fn oldest_proof(&self, block: u64) -> Result<ProverDetails> {
+        let mut details = self.store.oldest_proof(block).await?;
+        if let Some(hash) = &details.proof_hash {
+            details.proof = String::from_utf8(self.blobs.load(hash).await?)?;
+        }
+        Ok(details)
     }
 
     /// The post status rx always receives response about the proof that was posted to multiple L1s.
-    /// Once the proof posting is successful, this function saves that information to the posted collection.
-    /// For now, the previous proofs are not deleted, but that can be done later with this function
-    pub async fn run(&self, mut post_status_rx: Receiver<PostStatus>) -> Result<()> {
+    /// Once the proof posting is successful, this function saves that information in the settlement of the block.
+    /// Nothing is deleted here. Retention, see `DB::prune`, deletes the proofs of other provers at heights
+    /// settled on every L1 after `extra_proofs_days`, and rejected submissions after `rejected_submissions_days`.
+    pub async fn run(&self, post_status_rx: &mut Receiver<PostUpdate>) -> Result<()> {
         tracing::info!("Database service running");
-        while let Some(post_status) = post_status_rx.recv().await {
-            let block = post_status.block.to_string();
-            let chain = post_status.chain;
-            let posted = post_status.posted;
-            info_span!("", chain = chain, block = block);
-            tracing::info!(
-                "Command to update collection received after the data was posted to l1:{}",
-                chain
+        while let Some(update) = post_status_rx.recv().await {
+            match update {
+                PostUpdate::Attempt(attempt) => self.record_attempt(attempt).await?,
+                PostUpdate::Status(post_status) => self.update_post_status(post_status).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the transactions of a settlement attempt, or the error it failed with
+    pub async fn record_attempt(&self, attempt: SettlementAttempt) -> Result<()> {
+        let commit = attempt.txs.commit.as_ref().map(settlement_tx);
+        let finalize = attempt.txs.finalize.as_ref().map(settlement_tx);
+        let fee: u128 = attempt.txs.landed().iter().map(LandedTx::fee).sum();
+
+        self.store
+            .record_attempt(
+                &attempt.chain,
+                attempt.block,
+                commit,
+                finalize,
+                attempt.error.clone(),
+            )
+            .await?;
+        match attempt.error {
+            Some(error) => tracing::info!(
+                "Failed settlement attempt recorded. chain:{} block:{} error:{}",
+                attempt.chain,
+                attempt.block,
+                error
+            ),
+            None => tracing::info!(
+                "Settlement attempt recorded. chain:{} block:{} fee:{}wei",
+                attempt.chain,
+                attempt.block,
+                fee
+            ),
+        }
+        Ok(())
+    }
+
+    /// Saves the post result of a block for a chain in its settlement on the block
+    pub async fn update_post_status(&self, post_status: PostStatus) -> Result<()> {
+        let block = post_status.block.to_string();
+        let chain = post_status.chain;
+        let posted = post_status.posted;
+        info_span!("", chain = chain, block = block);
+        tracing::info!(
+            "Command to update collection received after the data was posted to l1:{}",
+            chain
+        );
+
+        self.store
+            .set_post_status(&chain, post_status.block, posted)
+            .await?;
+        tracing::info!(
+            "Proof post result added in db for chain:{} block:{}",
+            chain,
+            block
+        );
+        Ok(())
+    }
+
+    /// Brings the post status of a chain in line with the contract at its final block, as read by
+    /// `L1State::settled_batch`. Threshold verified blocks up to `last_finalized` are marked posted,
+    /// and blocks above it that are marked posted are reset.
+    pub async fn reconcile_l1_status(&self, chain: &str, last_finalized: u64) -> Result<()> {
+        let posted_status: HashMap<u64, bool> = self
+            .store
+            .post_statuses()
+            .await?
+            .into_iter()
+            .filter_map(|(height, chains)| chains.get(chain).map(|posted| (height, *posted)))
+            .collect();
+        let verified_heights = self.store.threshold_verified_blocks().await?;
+
+        let mut corrected = 0;
+        for height in verified_heights {
+            let finalized = height <= last_finalized;
+            if posted_status.get(&height) == Some(&finalized) {
+                continue;
+            }
+            if !finalized && posted_status.get(&height).is_none() {
+                continue;
+            }
+            tracing::warn!(
+                "Post status out of sync with contract. chain:{} block:{} posted:{}",
+                chain,
+                height,
+                finalized
             );
+            self.update_post_status(PostStatus {
+                chain: chain.to_string(),
+                block: height,
+                posted: finalized,
+            })
+            .await?;
+            corrected += 1;
+        }
 
-            let filter = doc! { format!("l1s.{}", block): { "$exists": true } };
-
-            // TODO: Verify later
-            match self.l1_collection.find_one(filter.clone()).await {
-                Ok(doc) => match doc {
-                    Some(mut doc) => {
-                        let l1_entry = doc.l1s.entry(block.clone()).or_insert_with(HashMap::new);
-                        l1_entry.insert(chain.clone(), posted);
-
-                        let update = doc! {
-                            "$set": {
-                                format!("l1s.{}", block): bson::to_bson(&doc.l1s)?,
-                            }
-                        };
-                        self.l1_collection.update_one(filter, update).await?;
-
-                        tracing::info!(
-                            "Proof post result added in db for chain:{} block:{}",
-                            chain,
-                            block
-                        );
-                    }
-                    None => {
-                        let mut poster_1 = HashMap::new();
-                        poster_1.insert(chain.clone(), posted);
-                        let mut l1s = HashMap::new();
-                        l1s.insert(block.clone(), poster_1);
-
-                        let final_struct = L1Details { l1s };
-                        let res = self.l1_collection.insert_one(final_struct).await?;
-                        tracing::info!(
-                            "Proof post result inserted to db at id: {} chain:{} height:{}",
-                            res.inserted_id,
-                            chain,
-                            block
-                        );
-                    }
-                },
+        tracing::info!(
+            "Reconciled post status with contract. chain:{} last_finalized:{} corrected:{}",
+            chain,
+            last_finalized,
+            corrected
+        );
+        Ok(())
+    }
+
+    /// Threshold verified blocks that are not marked posted on every chain, ready to hand back to
+    /// the poster after a restart
+    pub async fn unposted_batches(&self, chains: &[String]) -> Result<Vec<PostParams>> {
+        let posted = self.store.post_statuses().await?;
+        let mut heights: Vec<u64> = self
+            .store
+            .threshold_verified_blocks()
+            .await?
+            .into_iter()
+            .filter(|height| !settled_on(posted.get(height), chains))
+            .collect();
+        heights.sort_unstable();
+
+        let mut batches = vec![];
+        for height in heights {
+            let proof = match self.oldest_proof(height).await {
+                Ok(proof) => proof,
                 Err(e) => {
-                    tracing::error!("Error finding status error: {:?}", e.to_string());
-                    
-                    let mut poster_1 = HashMap::new();
-                    poster_1.insert(chain.clone(), posted);
-                    let mut l1s = HashMap::new();
-                    l1s.insert(block.clone(), poster_1);
-
-                    let final_struct = L1Details { l1s };
-                    let res = self.l1_collection.insert_one(final_struct).await?;
-                    tracing::info!(
-                        "Proof post result inserted to db at id: {} chain:{} height:{}",
-                        res.inserted_id,
-                        chain,
-                        block
+                    tracing::error!(
+                        "Failed to read proof of unposted batch. height:{} error:{}",
+                        height,
+                        e
                     );
+                    continue;
                 }
+            };
+            match make_post_params(&proof.proof_type, proof.proof, height) {
+                Ok(params) => batches.push(params),
+                Err(e) => tracing::error!(
+                    "Failed to rebuild unposted batch. height:{} error:{}",
+                    height,
+                    e
+                ),
             }
         }
-        Ok(())
+        Ok(batches)
     }
 
-    /// Get the first proof that was submitted to db for the block.
-    pub async fn find_oldest_proof(&self, block: String) -> Result<ProverDetails> {
-        let pipeline = vec![
-            doc! {
-                "$match": doc! {
-                    format!("blocks.{}.prover_details",block): doc! {
-                        "$exists": true
-                    }
+    /// Applies the retention policy. Proofs are only pruned at heights posted on every chain in
+    /// `chains`, and the proof that was posted is always kept. With `dry_run` nothing is removed,
+    /// the report says what would be.
+    pub async fn prune(
+        &self,
+        retention: &RetentionConfig,
+        chains: &[String],
+        dry_run: bool,
+    ) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+
+        if let (Some(days), false) = (retention.extra_proofs_days, chains.is_empty()) {
+            let before = days_ago(days);
+            let mut heights: Vec<u64> = self
+                .store
+                .post_statuses()
+                .await?
+                .into_iter()
+                .filter(|(_, statuses)| settled_on(Some(statuses), chains))
+                .map(|(height, _)| height)
+                .collect();
+            heights.sort_unstable();
+
+            for height in heights {
+                let selected = self.store.oldest_proof(height).await?;
+                let (extra, kept): (Vec<_>, Vec<_>) = self
+                    .store
+                    .proofs(height)
+                    .await?
+                    .into_iter()
+                    .partition(|(_, details)| {
+                        details.timestamp < before
+                            && (details.timestamp, &details.proof_hash)
+                                != (selected.timestamp, &selected.proof_hash)
+                    });
+                if extra.is_empty() {
+                    continue;
                 }
-            },
-            doc! {
-                "$project": doc! {
-                    "prover_details_array": doc! {
-                        "$objectToArray": format!("$blocks.{}.prover_details",block)
+
+                // Blobs are shared by identical payloads, only drop those nothing else points to
+                let mut blobs: Vec<String> = extra
+                    .iter()
+                    .filter_map(|(_, details)| details.proof_hash.clone())
+                    .filter(|hash| {
+                        !kept
+                            .iter()
+                            .any(|(_, d)| d.proof_hash.as_ref() == Some(hash))
+                    })
+                    .collect();
+                blobs.sort_unstable();
+                blobs.dedup();
+
+                let identifiers: Vec<String> = extra
+                    .into_iter()
+                    .map(|(identifier, _)| identifier)
+                    .collect();
+                if !dry_run {
+                    self.store.remove_proofs(height, &identifiers).await?;
+                    for hash in &blobs {
+                        self.blobs.delete(hash).await?;
                     }
                 }
-            },
-            doc! {
-                "$unwind": doc! {
-                    "path": "$prover_details_array"
-                }
-            },
-            doc! {
-                "$sort": doc! {
-                    "prover_details_array.v.timestamp": 1
-                }
-            },
-            doc! {
-                "$limit": 1
-            },
-        ];
-
-        let mut cursor = self.proof_collection.aggregate(pipeline).await?;
-
-        while let Some(doc) = cursor.next().await {
-            if let Ok(document) = doc {
-                if let Some(prover_detail_bson) = document
-                    .get("prover_details_array")
-                    .and_then(|pd_array| pd_array.as_document())
-                    .and_then(|pd| pd.get("v").and_then(|v| v.as_document()))
-                {
-                    let prover_details: ProverDetails =
-                        bson::from_bson(Bson::Document(prover_detail_bson.clone()))?;
-
-                    return Ok(prover_details);
-                }
+                report.blobs += blobs.len();
+                report.proofs.extend(
+                    identifiers
+                        .into_iter()
+                        .map(|identifier| (height, identifier)),
+                );
             }
         }
-        Err(Error::msg("Failed to fetch proofs"))
+
+        if let Some(days) = retention.rejected_submissions_days {
+            report.submissions = self
+                .store
+                .prune_submission_log(days_ago(days), dry_run)
+                .await?;
+        }
+
+        tracing::info!(
+            "Retention policy applied. dry_run:{} proofs:{} blobs:{} submissions:{}",
+            dry_run,
+            report.proofs.len(),
+            report.blobs,
+            report.submissions
+        );
+        Ok(report)
+    }
+
+    /// Appends the outcome of a proof submission to the log. A failure to write it is logged,
+    /// it does not change how the proof is handled.
+    pub async fn log_submission(&self, entry: SubmissionLog) {
+        tracing::info!(
+            "Proof submission. identifier:{:?} height:{:?} outcome:{:?} error:{:?}",
+            entry.identifier,
+            entry.height,
+            entry.outcome,
+            entry.error
+        );
+        if let Err(e) = self.store.log_submission(entry).await {
+            tracing::error!("Failed to write submission log. error:{}", e);
+        }
     }
 
     /// Should only arrive at this function ONLY IF the proof has been verified.
-    /// The first proof, a new document is created. For second and onwards, the document is updated,
-    /// In every step, threshold is checked. If threshold has reached, notify poster with block number
+    /// The proof is added to the block, and the submission that brings the block to the threshold
+    /// notifies the poster. Concurrent submissions for a block notify it exactly once.
     pub async fn save_proof_to_db(
         &self,
         identifier: String,
@@ -197,143 +468,299 @@ impl DB {
         block: u64,
         proof: String,
         poster_tx: Sender<PostParams>,
-    ) -> Result<()> {
-        let block_str = block.to_string();
-        let filter = doc! { format!("blocks.{}", block_str): { "$exists": true } };
-
-        match self.proof_collection.find_one(filter.clone()).await? {
-            Some(existing_block_details) => {
-                let mut blocks = existing_block_details.blocks;
-
-                // New prover detail
-                let prover_detail = ProverDetails {
-                    proof,
-                    proof_type: prover_type.to_string(),
-                    verified: true,
-                    timestamp: DateTime::now(),
-                };
-
-                let block_fields = blocks
-                    .get_mut(&block_str)
-                    .ok_or_else(|| Error::msg("Block fields missing"))?;
-
-                let threshold_was_verified = block_fields.threshold_verified;
-                if block_fields.prover_details.contains_key(&identifier) {
-                    tracing::info!(
-                        "Proof already submitted height:{} identifier:{}",
-                        block_str,
-                        identifier
-                    );
-                    return Ok(());
-                }
-                block_fields
-                    .prover_details
-                    .insert(identifier.clone(), prover_detail);
-
-                let threshold_verified = block_fields.prover_details.len() >= self.threshold;
-                block_fields.threshold_verified = threshold_verified;
-                block_fields.timestamp = DateTime::now();
-
-                let update = doc! {
-                    "$set": {
-                        format!("blocks.{}.prover_details", block_str): bson::to_bson(&block_fields.prover_details)?,
-                        format!("blocks.{}.threshold_verified", block_str): threshold_verified,
-                        format!("blocks.{}.timestamp", block_str): block_fields.timestamp,
-                    }
-                };
+    ) -> Result<ProofInsert> {
+        // the blob is stored once the proof is in, so a duplicate leaves nothing behind
+        let prover_detail = ProverDetails {
+            proof: String::new(),
+            proof_hash: Some(Blobs::hash(proof.as_bytes())),
+            proof_type: prover_type.to_string(),
+            verified: true,
+            timestamp: DateTime::now(),
+        };
 
-                self.proof_collection.update_one(filter, update).await?;
+        let insert = self
+            .store
+            .add_proof(block, &identifier, prover_detail)
+            .await?;
+        let provers = match insert {
+            ProofInsert::Duplicate => {
                 tracing::info!(
-                    "Proof updated in db for block: {} identifier:{}",
-                    block_str.clone(),
+                    "Proof already submitted height:{} identifier:{}",
+                    block,
                     identifier
                 );
-                if !threshold_was_verified {
-                    let proof: ProverDetails;
-                    let mut count = 1;
-                    loop {
-                        match self.find_oldest_proof(block_str.clone()).await {
-                            Ok(p) => {
-                                proof = p;
-                                break;
-                            }
-                            Err(e) => {
-                                if count > MAX_RETRIES {
-                                    return Err(e);
-                                }
-                                count += 1;
-                            }
-                        }
-                    }
-
-                    // Notify Poster It's ready to send proof for the block
-                    if threshold_verified {
-                        tracing::info!("Threshold verified for block: {}", block_str);
-                        let params = match SupportedProvers::from_str(&proof.proof_type)? {
-                            SupportedProvers::SP1 => SP1::process_proof(proof.proof, block),
-                            SupportedProvers::RISC0 => todo!(),
-                            SupportedProvers::Dummy => {
-                                let proof_bytes = hex::decode(proof.proof)?;
-                                let params_inner = DummyParams { proof: proof_bytes };
-                                Ok(PostParams::Dummy(params_inner, block))
-                            }
-                        };
-                        if let Ok(ref param) = params {
-                            poster_tx.send(param.clone()).await?;
-                        }
-                    }
-                }
+                return Ok(insert);
             }
-            None => {
-                tracing::info!("Instance of that proof not found. Creating a new document!");
-                let prover_detail = ProverDetails {
-                    proof: proof.clone(),
-                    proof_type: prover_type.to_string(),
-                    verified: true,
-                    timestamp: DateTime::now(),
-                };
-                let mut prover_details = HashMap::new();
-                prover_details.insert(identifier.clone(), prover_detail);
-
-                let threshold_verified = self.threshold == 1;
-
-                let block_fields = BlockFields {
-                    prover_details,
-                    threshold_verified,
-                    timestamp: DateTime::now(),
-                };
-
-                let mut blocks = HashMap::new();
-                blocks.insert(block_str.clone(), block_fields);
-
-                let final_struct = ProofDetails { blocks };
-
-                // required structure is now ready
-                tracing::info!("Ready to post to db");
-                let res = self.proof_collection.insert_one(final_struct).await?;
-                tracing::info!(
-                    "Proof inserted to db at id: {} height:{} identifier:{}",
-                    res.inserted_id,
-                    block_str,
-                    identifier
-                );
+            ProofInsert::Added { provers } => provers,
+        };
+        if let Err(e) = self.blobs.store(proof.as_bytes()).await {
+            self.store.remove_proofs(block, &[identifier]).await?;
+            return Err(e);
+        }
+        tracing::info!(
+            "Proof saved to db. height:{} identifier:{} provers:{}",
+            block,
+            identifier,
+            provers
+        );
+
+        // the claim is atomic in the store, so only one submission notifies the poster
+        if provers < self.threshold() || !self.store.claim_threshold(block).await? {
+            return Ok(insert);
+        }
+        tracing::info!("Threshold verified for block: {}", block);
+
+        // the claim is already taken, a block that is not handed over here is handed to the
+        // poster from `unposted_batches` on the next start
+        if let Err(e) = self.hand_to_poster(block, poster_tx).await {
+            tracing::error!(
+                "Threshold verified block not handed to the poster, it is retried on restart. height:{} error:{}",
+                block,
+                e
+            );
+            return Err(e);
+        }
+        Ok(insert)
+    }
 
-                if threshold_verified {
-                    let params = match prover_type {
-                        SupportedProvers::SP1 => SP1::process_proof(proof, block),
-                        SupportedProvers::RISC0 => todo!(),
-                        SupportedProvers::Dummy => {
-                            let proof_bytes = hex::decode(proof)?;
-                            let params_inner = DummyParams { proof: proof_bytes };
-                            Ok(PostParams::Dummy(params_inner, block_str.parse()?))
-                        }
-                    };
-                    if let Ok(ref param) = params {
-                        poster_tx.send(param.clone()).await?;
+    /// Sends the post parameters of a threshold verified block to the poster
+    async fn hand_to_poster(&self, block: u64, poster_tx: Sender<PostParams>) -> Result<()> {
+        let mut backoff = READ_BACKOFF;
+        let mut count = 1;
+        let proof = loop {
+            match self.oldest_proof(block).await {
+                Ok(proof) => break proof,
+                Err(e) => {
+                    if count > MAX_RETRIES {
+                        return Err(e);
                     }
+                    tracing::warn!(
+                        "Failed to read proof of claimed block. height:{} attempt:{} error:{}",
+                        block,
+                        count,
+                        e
+                    );
+                    sleep(backoff).await;
+                    backoff *= 2;
+                    count += 1;
                 }
             }
-        }
+        };
+
+        let params = make_post_params(&proof.proof_type, proof.proof, block)?;
+        poster_tx.send(params).await?;
         Ok(())
     }
 }
+
+fn settlement_tx(tx: &LandedTx) -> SettlementTx {
+    SettlementTx {
+        tx_hash: tx.tx_hash.clone(),
+        l1_block: tx.block_number,
+        gas_used: tx.gas_used,
+        effective_gas_price: tx.effective_gas_price.to_string(),
+        fee: tx.fee().to_string(),
+        broadcasts: tx.broadcasts,
+        landed_at: DateTime::now(),
+    }
+}
+
+/// Builds the contract call parameters from a stored proof
+fn make_post_params(proof_type: &str, proof: String, block: u64) -> Result<PostParams> {
+    match SupportedProvers::from_str(proof_type)? {
+        SupportedProvers::SP1 => SP1::process_proof(proof, block),
+        SupportedProvers::RISC0 => Err(Error::msg("RISC0 proofs cannot be posted")),
+        SupportedProvers::Dummy => {
+            let proof_bytes = hex::decode(proof)?;
+            let params_inner = DummyParams { proof: proof_bytes };
+            Ok(PostParams::Dummy(params_inner, block))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use mongodb::bson::DateTime;
+
+    use super::{days_ago, Collection, DeleteScope, DB};
+    use crate::{
+        config::RetentionConfig,
+        database::{
+            blob::{BlobStore, Blobs},
+            schema::ProverDetails,
+            storage::Storage,
+        },
+        types::SupportedProvers,
+    };
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_proofs_notify_once() {
+        let db = Arc::new(DB::new(2, "memory://".to_string(), None).await);
+        let (poster_tx, mut poster_rx) = mpsc::channel(10);
+
+        let mut tasks = vec![];
+        for prover in 0..8 {
+            let db = Arc::clone(&db);
+            let poster_tx = poster_tx.clone();
+            tasks.push(tokio::spawn(async move {
+                db.save_proof_to_db(
+                    format!("prover-{}", prover),
+                    SupportedProvers::Dummy,
+                    9,
+                    hex::encode([0, 9]),
+                    poster_tx,
+                )
+                .await
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        drop(poster_tx);
+
+        assert_eq!(poster_rx.recv().await.unwrap().height(), 9);
+        assert!(poster_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_hand_off_is_retried_on_restart() {
+        let db = DB::new(1, "memory://".to_string(), None).await;
+        let (poster_tx, poster_rx) = mpsc::channel(10);
+        drop(poster_rx);
+
+        let saved = db
+            .save_proof_to_db(
+                "prover".to_string(),
+                SupportedProvers::Dummy,
+                4,
+                hex::encode([0, 4]),
+                poster_tx,
+            )
+            .await;
+        assert!(saved.is_err());
+
+        let unposted = db.unposted_batches(&["bnb".to_string()]).await.unwrap();
+        assert_eq!(unposted.len(), 1);
+        assert_eq!(unposted[0].height(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_unposted_batches_skip_missing_blob() {
+        let db = DB::new(1, "memory://".to_string(), None).await;
+        let (poster_tx, _poster_rx) = mpsc::channel(10);
+        for block in [1u8, 2] {
+            db.save_proof_to_db(
+                "prover".to_string(),
+                SupportedProvers::Dummy,
+                block as u64,
+                hex::encode([0, block]),
+                poster_tx.clone(),
+            )
+            .await
+            .unwrap();
+        }
+        db.blobs
+            .delete(&Blobs::hash(hex::encode([0, 1]).as_bytes()))
+            .await
+            .unwrap();
+
+        let unposted = db.unposted_batches(&["bnb".to_string()]).await.unwrap();
+        assert_eq!(unposted.len(), 1);
+        assert_eq!(unposted[0].height(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_proof_stores_no_blob() {
+        let db = DB::new(2, "memory://".to_string(), None).await;
+        let (poster_tx, _poster_rx) = mpsc::channel(10);
+        for proof in [[0, 1], [0, 2]] {
+            db.save_proof_to_db(
+                "prover".to_string(),
+                SupportedProvers::Dummy,
+                1,
+                hex::encode(proof),
+                poster_tx.clone(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let duplicate = Blobs::hash(hex::encode([0, 2]).as_bytes());
+        assert!(db.blobs.get(&duplicate).await.unwrap().is_none());
+        assert_eq!(db.oldest_proof(1).await.unwrap().proof, hex::encode([0, 1]));
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_posted_proof() {
+        let db = DB::new(2, "memory://".to_string(), None).await;
+        for (prover, millis) in [("first", 1000), ("second", 2000), ("third", 3000)] {
+            let details = ProverDetails {
+                proof: String::new(),
+                proof_hash: Some(db.blobs.store(prover.as_bytes()).await.unwrap()),
+                proof_type: SupportedProvers::Dummy.to_string(),
+                verified: true,
+                timestamp: DateTime::from_millis(millis),
+            };
+            db.store.add_proof(5, prover, details).await.unwrap();
+        }
+        let retention = RetentionConfig {
+            extra_proofs_days: Some(1),
+            ..Default::default()
+        };
+        let chains = vec!["a".to_string(), "b".to_string()];
+
+        db.store.set_post_status("a", 5, true).await.unwrap();
+        let report = db.prune(&retention, &chains, false).await.unwrap();
+        assert!(report.proofs.is_empty());
+
+        db.store.set_post_status("b", 5, true).await.unwrap();
+        let report = db.prune(&retention, &chains, true).await.unwrap();
+        assert_eq!(report.proofs.len(), 2);
+        assert_eq!(db.store.proofs(5).await.unwrap().len(), 3);
+
+        let report = db.prune(&retention, &chains, false).await.unwrap();
+        assert_eq!(report.blobs, 2);
+        let remaining = db.store.proofs(5).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].0, "first");
+        assert_eq!(db.oldest_proof(5).await.unwrap().proof, "first");
+
+        assert!(days_ago(u64::MAX) < days_ago(1));
+        assert!(days_ago(1) < DateTime::now());
+    }
+
+    #[tokio::test]
+    async fn test_delete_scoped_keeps_settled_blocks() {
+        let db = DB::new(1, "memory://".to_string(), None).await;
+        let (poster_tx, _poster_rx) = mpsc::channel(10);
+        for block in [3, 4, 5] {
+            db.save_proof_to_db(
+                "prover".to_string(),
+                SupportedProvers::Dummy,
+                block,
+                hex::encode([0, block as u8]),
+                poster_tx.clone(),
+            )
+            .await
+            .unwrap();
+        }
+        db.store.set_post_status("bnb", 3, true).await.unwrap();
+        let chains = vec!["bnb".to_string()];
+
+        let scope = DeleteScope {
+            to: Some(4),
+            collection: Some(Collection::Blocks),
+            unsettled: true,
+            ..Default::default()
+        };
+        assert!(!scope.is_everything());
+        let report = db.delete_scoped(&scope, &chains).await.unwrap();
+        assert_eq!((report.blocks, report.blobs), (1, 1));
+        assert_eq!(db.store.heights(0, 10).await.unwrap(), [3, 5]);
+        assert!(db.oldest_proof(3).await.is_ok());
+    }
+}
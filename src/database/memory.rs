@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Error, Result};
+use mongodb::bson::DateTime;
+
+use super::{
+    schema::{ProverDetails, Settlement, SettlementTx, SubmissionLog, SubmissionOutcome},
+    storage::{ProofInsert, Storage, SubmissionQuery},
+};
+
+#[derive(Default)]
+struct BlockEntry {
+    provers: HashMap<String, ProverDetails>,
+    threshold_verified: bool,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    blocks: HashMap<u64, BlockEntry>,
+    settlements: HashMap<u64, HashMap<String, Settlement>>,
+    submission_log: Vec<SubmissionLog>,
+}
+
+impl MemoryStore {
+    fn modify_settlement(&self, chain: &str, block: u64, modify: impl FnOnce(&mut Settlement)) {
+        let mut state = self.state.lock().unwrap();
+        let settlement = state
+            .settlements
+            .entry(block)
+            .or_default()
+            .entry(chain.to_string())
+            .or_insert_with(|| Settlement::new(chain));
+        modify(settlement);
+    }
+}
+
+/// Keeps everything in process memory, for tests and runs that do not need to survive a restart
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl Storage for MemoryStore {
+    async fn add_proof(
+        &self,
+        block: u64,
+        identifier: &str,
+        details: ProverDetails,
+    ) -> Result<ProofInsert> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.blocks.entry(block).or_default();
+        if entry.provers.contains_key(identifier) {
+            return Ok(ProofInsert::Duplicate);
+        }
+        entry.provers.insert(identifier.to_string(), details);
+        Ok(ProofInsert::Added {
+            provers: entry.provers.len(),
+        })
+    }
+
+    async fn claim_threshold(&self, block: u64) -> Result<bool> {
+        match self.state.lock().unwrap().blocks.get_mut(&block) {
+            Some(entry) if !entry.threshold_verified => {
+                entry.threshold_verified = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn oldest_proof(&self, block: u64) -> Result<ProverDetails> {
+        self.state
+            .lock()
+            .unwrap()
+            .blocks
+            .get(&block)
+            .and_then(|entry| entry.provers.values().min_by_key(|p| p.timestamp))
+            .cloned()
+            .ok_or_else(|| Error::msg("Failed to fetch proofs"))
+    }
+
+    async fn proofs(&self, block: u64) -> Result<Vec<(String, ProverDetails)>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .blocks
+            .get(&block)
+            .map(|entry| {
+                entry
+                    .provers
+                    .iter()
+                    .map(|(identifier, details)| (identifier.clone(), details.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn remove_proofs(&self, block: u64, identifiers: &[String]) -> Result<()> {
+        if let Some(entry) = self.state.lock().unwrap().blocks.get_mut(&block) {
+            entry
+                .provers
+                .retain(|identifier, _| !identifiers.contains(identifier));
+        }
+        Ok(())
+    }
+
+    async fn threshold_verified_blocks(&self) -> Result<Vec<u64>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .blocks
+            .iter()
+            .filter(|(_, entry)| entry.threshold_verified)
+            .map(|(block, _)| *block)
+            .collect())
+    }
+
+    async fn heights(&self, from: u64, to: u64) -> Result<Vec<u64>> {
+        let state = self.state.lock().unwrap();
+        let mut heights: Vec<u64> = state
+            .blocks
+            .keys()
+            .chain(state.settlements.keys())
+            .copied()
+            .filter(|height| (from..=to).contains(height))
+            .collect();
+        heights.sort_unstable();
+        heights.dedup();
+        Ok(heights)
+    }
+
+    async fn set_post_status(&self, chain: &str, block: u64, posted: bool) -> Result<()> {
+        self.modify_settlement(chain, block, |settlement| settlement.set_posted(posted));
+        Ok(())
+    }
+
+    async fn record_attempt(
+        &self,
+        chain: &str,
+        block: u64,
+        commit: Option<SettlementTx>,
+        finalize: Option<SettlementTx>,
+        error: Option<String>,
+    ) -> Result<()> {
+        self.modify_settlement(chain, block, |settlement| {
+            settlement.record_attempt(commit, finalize, error)
+        });
+        Ok(())
+    }
+
+    async fn settlements(&self, block: u64) -> Result<Vec<Settlement>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .settlements
+            .get(&block)
+            .map(|chains| chains.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn restore_settlement(&self, block: u64, settlement: Settlement) -> Result<()> {
+        let chain = settlement.chain.clone();
+        self.modify_settlement(&chain, block, |stored| *stored = settlement);
+        Ok(())
+    }
+
+    async fn post_statuses(&self) -> Result<HashMap<u64, HashMap<String, bool>>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .settlements
+            .iter()
+            .map(|(block, chains)| {
+                let statuses = chains
+                    .iter()
+                    .map(|(chain, settlement)| (chain.clone(), settlement.posted))
+                    .collect();
+                (*block, statuses)
+            })
+            .collect())
+    }
+
+    async fn log_submission(&self, entry: SubmissionLog) -> Result<()> {
+        self.state.lock().unwrap().submission_log.push(entry);
+        Ok(())
+    }
+
+    async fn submissions(&self, query: SubmissionQuery) -> Result<Vec<SubmissionLog>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .submission_log
+            .iter()
+            .rev()
+            .filter(|entry| query.matches(entry))
+            .take(query.limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn prune_submission_log(&self, before: DateTime, dry_run: bool) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let prunable = |entry: &SubmissionLog| {
+            entry.outcome != SubmissionOutcome::Accepted && entry.timestamp < before
+        };
+        let count = state
+            .submission_log
+            .iter()
+            .filter(|entry| prunable(entry))
+            .count() as u64;
+        if !dry_run {
+            state.submission_log.retain(|entry| !prunable(entry));
+        }
+        Ok(count)
+    }
+
+    async fn delete_blocks(&self, heights: &[u64]) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let mut deleted = 0;
+        for height in heights {
+            let block = state.blocks.remove(height).is_some();
+            let settlements = state.settlements.remove(height).is_some();
+            deleted += (block || settlements) as u64;
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_submissions(&self, range: Option<(u64, u64)>) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let before = state.submission_log.len();
+        match range {
+            Some((from, to)) => state.submission_log.retain(|entry| {
+                !entry
+                    .height
+                    .is_some_and(|height| (from..=to).contains(&height))
+            }),
+            None => state.submission_log.clear(),
+        }
+        Ok((before - state.submission_log.len()) as u64)
+    }
+
+    async fn delete_all(&self) -> Result<()> {
+        *self.state.lock().unwrap() = MemoryState::default();
+        Ok(())
+    }
+}
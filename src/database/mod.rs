@@ -1,2 +1,9 @@
+pub mod archive;
+pub mod blob;
 pub mod db;
+pub mod memory;
+pub mod mongo;
+pub mod pruner;
 pub mod schema;
+pub mod sql;
+pub mod storage;
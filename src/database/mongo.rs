@@ -0,0 +1,538 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Error, Result};
+use futures::stream::{StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{self, doc, DateTime, Document},
+    error::{ErrorKind, WriteFailure},
+    gridfs::GridFsBucket,
+    options::ReturnDocument,
+    Client, Collection, Database, IndexModel,
+};
+use serde::Deserialize;
+
+use super::{
+    schema::{
+        BlockRecord, L1Details, ProofDetails, ProverDetails, Settlement, SettlementTx, Submission,
+        SubmissionLog, SubmissionOutcome,
+    },
+    storage::{ProofInsert, Storage, SubmissionQuery},
+};
+
+static DB_NAME: &str = "twine_aggregator";
+static BLOCK_COLLECTION_NAME: &str = "blocks";
+static SUBMISSION_LOG_COLLECTION_NAME: &str = "submission_log";
+static LEGACY_PROOF_COLLECTION_NAME: &str = "proof_collection";
+static LEGACY_POSTER_COLLECTION_NAME: &str = "l1s_collection";
+static DUPLICATE_KEY: i32 = 11000;
+
+async fn connect_to_mongodb(uri: &str) -> mongodb::error::Result<Database> {
+    let client = Client::with_uri_str(uri).await?;
+    let database = client.database(DB_NAME);
+    Ok(database)
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::Command(command) => command.code == DUPLICATE_KEY,
+        ErrorKind::Write(WriteFailure::WriteError(write)) => write.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+/// Settlements of a block, without the proofs
+#[derive(Deserialize)]
+struct SettlementsOnly {
+    #[serde(rename = "_id")]
+    height: u64,
+    settlements: Vec<Settlement>,
+}
+
+pub struct MongoStore {
+    database: Database,
+    pub blocks: Collection<BlockRecord>,
+    pub submission_log: Collection<SubmissionLog>,
+}
+
+impl MongoStore {
+    pub async fn connect(uri: &str) -> Result<Self> {
+        let database = connect_to_mongodb(uri).await?;
+        let store = Self {
+            blocks: database.collection(BLOCK_COLLECTION_NAME),
+            submission_log: database.collection(SUBMISSION_LOG_COLLECTION_NAME),
+            database,
+        };
+        store.create_indexes().await?;
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    /// Bucket for proof blobs, in the aggregator database
+    pub fn gridfs(&self) -> GridFsBucket {
+        self.database.gridfs_bucket(None)
+    }
+
+    /// Heights are the document ids. These cover the threshold and settlement lookups,
+    /// and the submission log by prover and by height.
+    async fn create_indexes(&self) -> Result<()> {
+        self.blocks
+            .create_indexes([
+                IndexModel::builder()
+                    .keys(doc! { "threshold_verified": 1 })
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "settlements.chain": 1, "settlements.posted": 1 })
+                    .build(),
+            ])
+            .await?;
+        self.submission_log
+            .create_indexes([
+                IndexModel::builder()
+                    .keys(doc! { "identifier": 1, "timestamp": -1 })
+                    .build(),
+                IndexModel::builder()
+                    .keys(doc! { "height": 1, "timestamp": -1 })
+                    .build(),
+            ])
+            .await?;
+        Ok(())
+    }
+
+    /// Moves data from the collections keyed by height strings into one document per block,
+    /// then drops them. Safe to re-run if it was interrupted.
+    async fn migrate(&self) -> Result<()> {
+        let legacy_proofs: Collection<ProofDetails> =
+            self.database.collection(LEGACY_PROOF_COLLECTION_NAME);
+        let legacy_posts: Collection<L1Details> =
+            self.database.collection(LEGACY_POSTER_COLLECTION_NAME);
+        if legacy_proofs.estimated_document_count().await? == 0
+            && legacy_posts.estimated_document_count().await? == 0
+        {
+            return Ok(());
+        }
+        tracing::info!("Migrating proofs and post status to one document per block");
+
+        let mut records: BTreeMap<u64, BlockRecord> = BTreeMap::new();
+        let mut cursor = legacy_proofs.find(doc! {}).await?;
+        while let Some(doc) = cursor.next().await {
+            for (block, fields) in doc?.blocks {
+                let Ok(height) = block.parse::<u64>() else {
+                    continue;
+                };
+                let record = records
+                    .entry(height)
+                    .or_insert_with(|| empty_record(height, fields.timestamp));
+                record.threshold_verified |= fields.threshold_verified;
+                record.timestamp = record.timestamp.max(fields.timestamp);
+                for (identifier, details) in fields.prover_details {
+                    add_submission(
+                        &mut record.submissions,
+                        Submission::new(&identifier, details),
+                    );
+                }
+            }
+        }
+
+        let mut cursor = legacy_posts.find(doc! {}).await?;
+        while let Some(doc) = cursor.next().await {
+            for (block, chains) in doc?.l1s {
+                let Ok(height) = block.parse::<u64>() else {
+                    continue;
+                };
+                let record = records
+                    .entry(height)
+                    .or_insert_with(|| empty_record(height, DateTime::now()));
+                for (chain, posted) in chains {
+                    let settlement = Settlement {
+                        posted,
+                        ..Settlement::new(&chain)
+                    };
+                    set_settlement(&mut record.settlements, settlement);
+                }
+            }
+        }
+
+        let migrated = records.len();
+        for (height, mut record) in records {
+            // merge with what was written since, e.g. by an interrupted earlier run
+            if let Some(existing) = self.blocks.find_one(doc! { "_id": height as i64 }).await? {
+                record.threshold_verified |= existing.threshold_verified;
+                for submission in existing.submissions {
+                    add_submission(&mut record.submissions, submission);
+                }
+                for settlement in existing.settlements {
+                    set_settlement(&mut record.settlements, settlement);
+                }
+            }
+            self.blocks
+                .replace_one(doc! { "_id": height as i64 }, record)
+                .upsert(true)
+                .await?;
+        }
+
+        legacy_proofs.drop().await?;
+        legacy_posts.drop().await?;
+        tracing::info!("Migration done. blocks:{}", migrated);
+        Ok(())
+    }
+
+    /// Runs the upsert again if it lost the race to create the document of a new block.
+    /// None if the filter still does not match the existing document.
+    async fn upsert(&self, filter: Document, update: Document) -> Result<Option<BlockRecord>> {
+        for _ in 0..2 {
+            match self
+                .blocks
+                .find_one_and_update(filter.clone(), update.clone())
+                .upsert(true)
+                .return_document(ReturnDocument::After)
+                .await
+            {
+                Ok(record) => return Ok(record),
+                Err(e) if is_duplicate_key(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn find_settlements(&self, block: u64) -> Result<Vec<Settlement>> {
+        Ok(self
+            .blocks
+            .clone_with_type::<SettlementsOnly>()
+            .find_one(doc! { "_id": block as i64 })
+            .projection(doc! { "settlements": 1 })
+            .await?
+            .map(|record| record.settlements)
+            .unwrap_or_default())
+    }
+
+    /// Applies `update` to the settlement of the block on the chain in a single write, so the poster,
+    /// the finality watcher and reconcile can all update it. `update` addresses the settlement as
+    /// `settlements.$[s]`, `array_filters` declares `s` and any other identifier it uses.
+    /// Without a settlement on the chain yet, `initial` is added as its first one.
+    async fn update_settlement(
+        &self,
+        chain: &str,
+        block: u64,
+        update: Document,
+        array_filters: Vec<Document>,
+        initial: Settlement,
+    ) -> Result<()> {
+        let initial = bson::to_bson(&initial)?;
+        for _ in 0..2 {
+            let result = self
+                .blocks
+                .update_one(
+                    doc! { "_id": block as i64, "settlements.chain": chain },
+                    update.clone(),
+                )
+                .array_filters(array_filters.clone())
+                .await?;
+            if result.matched_count == 1 {
+                return Ok(());
+            }
+
+            // first settlement of the block on this chain
+            let filter = doc! { "_id": block as i64, "settlements.chain": { "$ne": chain } };
+            let push = doc! {
+                "$push": { "settlements": initial.clone() },
+                "$setOnInsert": {
+                    "threshold_verified": false,
+                    "timestamp": DateTime::now(),
+                    "submissions": [],
+                },
+            };
+            if self.upsert(filter, push).await?.is_some() {
+                return Ok(());
+            }
+            // another writer added it first, update that one
+        }
+        Err(Error::msg(format!(
+            "Failed to update settlement. chain:{} block:{}",
+            chain, block
+        )))
+    }
+}
+
+/// Array filter matching the settlement of the chain as `s`
+fn settlement_filter(chain: &str) -> Document {
+    doc! { "s.chain": chain }
+}
+
+fn empty_record(height: u64, timestamp: DateTime) -> BlockRecord {
+    BlockRecord {
+        height,
+        threshold_verified: false,
+        timestamp,
+        submissions: vec![],
+        settlements: vec![],
+    }
+}
+
+fn add_submission(submissions: &mut Vec<Submission>, submission: Submission) {
+    if !submissions
+        .iter()
+        .any(|s| s.identifier == submission.identifier)
+    {
+        submissions.push(submission);
+    }
+}
+
+fn set_settlement(settlements: &mut Vec<Settlement>, settlement: Settlement) {
+    match settlements.iter_mut().find(|s| s.chain == settlement.chain) {
+        Some(existing) => *existing = settlement,
+        None => settlements.push(settlement),
+    }
+}
+
+impl Storage for MongoStore {
+    /// Adds the submission, or creates the block document, in a single atomic operation.
+    /// A block that already has a submission from this prover does not match the filter,
+    /// so the upsert fails on the duplicate id.
+    async fn add_proof(
+        &self,
+        block: u64,
+        identifier: &str,
+        details: ProverDetails,
+    ) -> Result<ProofInsert> {
+        let filter = doc! { "_id": block as i64, "submissions.identifier": { "$ne": identifier } };
+        let update = doc! {
+            "$push": { "submissions": bson::to_bson(&Submission::new(identifier, details))? },
+            "$set": { "timestamp": DateTime::now() },
+            "$setOnInsert": { "threshold_verified": false, "settlements": [] },
+        };
+
+        match self.upsert(filter, update).await? {
+            Some(record) => Ok(ProofInsert::Added {
+                provers: record.submissions.len(),
+            }),
+            None => Ok(ProofInsert::Duplicate),
+        }
+    }
+
+    async fn claim_threshold(&self, block: u64) -> Result<bool> {
+        let filter = doc! { "_id": block as i64, "threshold_verified": false };
+        let update = doc! { "$set": { "threshold_verified": true } };
+        let result = self.blocks.update_one(filter, update).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn oldest_proof(&self, block: u64) -> Result<ProverDetails> {
+        self.blocks
+            .find_one(doc! { "_id": block as i64 })
+            .await?
+            .and_then(|record| {
+                record
+                    .submissions
+                    .iter()
+                    .min_by_key(|s| s.timestamp)
+                    .map(Submission::details)
+            })
+            .ok_or_else(|| Error::msg("Failed to fetch proofs"))
+    }
+
+    async fn proofs(&self, block: u64) -> Result<Vec<(String, ProverDetails)>> {
+        Ok(self
+            .blocks
+            .find_one(doc! { "_id": block as i64 })
+            .await?
+            .map(|record| {
+                record
+                    .submissions
+                    .iter()
+                    .map(|s| (s.identifier.clone(), s.details()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn remove_proofs(&self, block: u64, identifiers: &[String]) -> Result<()> {
+        let update = doc! {
+            "$pull": { "submissions": { "identifier": { "$in": identifiers } } }
+        };
+        self.blocks
+            .update_one(doc! { "_id": block as i64 }, update)
+            .await?;
+        Ok(())
+    }
+
+    async fn threshold_verified_blocks(&self) -> Result<Vec<u64>> {
+        let ids: Vec<Document> = self
+            .blocks
+            .clone_with_type::<Document>()
+            .find(doc! { "threshold_verified": true })
+            .projection(doc! { "_id": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| id.get_i64("_id").ok())
+            .map(|height| height as u64)
+            .collect())
+    }
+
+    async fn heights(&self, from: u64, to: u64) -> Result<Vec<u64>> {
+        let ids: Vec<Document> = self
+            .blocks
+            .clone_with_type::<Document>()
+            .find(doc! { "_id": { "$gte": from as i64, "$lte": to as i64 } })
+            .projection(doc! { "_id": 1 })
+            .sort(doc! { "_id": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| id.get_i64("_id").ok())
+            .map(|height| height as u64)
+            .collect())
+    }
+
+    async fn set_post_status(&self, chain: &str, block: u64, posted: bool) -> Result<()> {
+        let mut initial = Settlement::new(chain);
+        initial.set_posted(posted);
+        let update = doc! { "$set": {
+            "settlements.$[s].posted": posted,
+            "settlements.$[s].finalized_at": initial.finalized_at,
+            "settlements.$[s].updated": initial.updated,
+        } };
+        self.update_settlement(
+            chain,
+            block,
+            update,
+            vec![settlement_filter(chain)],
+            initial,
+        )
+        .await
+    }
+
+    async fn record_attempt(
+        &self,
+        chain: &str,
+        block: u64,
+        commit: Option<SettlementTx>,
+        finalize: Option<SettlementTx>,
+        error: Option<String>,
+    ) -> Result<()> {
+        let mut set = doc! {
+            "settlements.$[s].last_error": error.clone(),
+            "settlements.$[s].updated": DateTime::now(),
+            // only the first attempt sets it, through the `first` filter
+            "settlements.$[first].first_attempt": DateTime::now(),
+        };
+        if let Some(commit) = &commit {
+            set.insert("settlements.$[s].commit", bson::to_bson(commit)?);
+        }
+        if let Some(finalize) = &finalize {
+            set.insert("settlements.$[s].finalize", bson::to_bson(finalize)?);
+        }
+        let update = doc! { "$set": set, "$inc": { "settlements.$[s].attempts": 1 } };
+        let array_filters = vec![
+            settlement_filter(chain),
+            doc! { "first.chain": chain, "first.first_attempt": null },
+        ];
+
+        let mut initial = Settlement::new(chain);
+        initial.record_attempt(commit, finalize, error);
+        self.update_settlement(chain, block, update, array_filters, initial)
+            .await
+    }
+
+    async fn settlements(&self, block: u64) -> Result<Vec<Settlement>> {
+        self.find_settlements(block).await
+    }
+
+    async fn restore_settlement(&self, block: u64, settlement: Settlement) -> Result<()> {
+        let chain = settlement.chain.clone();
+        let update = doc! { "$set": { "settlements.$[s]": bson::to_bson(&settlement)? } };
+        self.update_settlement(
+            &chain,
+            block,
+            update,
+            vec![settlement_filter(&chain)],
+            settlement,
+        )
+        .await
+    }
+
+    async fn post_statuses(&self) -> Result<HashMap<u64, HashMap<String, bool>>> {
+        let records: Vec<SettlementsOnly> = self
+            .blocks
+            .clone_with_type::<SettlementsOnly>()
+            .find(doc! { "settlements.0": { "$exists": true } })
+            .projection(doc! { "settlements": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(records
+            .into_iter()
+            .map(|record| {
+                let chains = record
+                    .settlements
+                    .into_iter()
+                    .map(|s| (s.chain, s.posted))
+                    .collect();
+                (record.height, chains)
+            })
+            .collect())
+    }
+
+    async fn log_submission(&self, entry: SubmissionLog) -> Result<()> {
+        self.submission_log.insert_one(entry).await?;
+        Ok(())
+    }
+
+    async fn submissions(&self, query: SubmissionQuery) -> Result<Vec<SubmissionLog>> {
+        let mut filter = doc! {};
+        if let Some(identifier) = &query.identifier {
+            filter.insert("identifier", identifier);
+        }
+        if let Some(height) = query.height {
+            filter.insert("height", height as i64);
+        }
+        Ok(self
+            .submission_log
+            .find(filter)
+            .sort(doc! { "timestamp": -1 })
+            .limit(query.limit as i64)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn prune_submission_log(&self, before: DateTime, dry_run: bool) -> Result<u64> {
+        let filter = doc! {
+            "outcome": { "$ne": bson::to_bson(&SubmissionOutcome::Accepted)? },
+            "timestamp": { "$lt": before },
+        };
+        if dry_run {
+            return Ok(self.submission_log.count_documents(filter).await?);
+        }
+        Ok(self.submission_log.delete_many(filter).await?.deleted_count)
+    }
+
+    async fn delete_blocks(&self, heights: &[u64]) -> Result<u64> {
+        let ids: Vec<i64> = heights.iter().map(|height| *height as i64).collect();
+        Ok(self
+            .blocks
+            .delete_many(doc! { "_id": { "$in": ids } })
+            .await?
+            .deleted_count)
+    }
+
+    async fn delete_submissions(&self, range: Option<(u64, u64)>) -> Result<u64> {
+        let filter = match range {
+            Some((from, to)) => doc! { "height": { "$gte": from as i64, "$lte": to as i64 } },
+            None => doc! {},
+        };
+        Ok(self.submission_log.delete_many(filter).await?.deleted_count)
+    }
+
+    /// Drops the blocks collection and the submission log
+    async fn delete_all(&self) -> Result<()> {
+        self.blocks.drop().await?;
+        self.submission_log.drop().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use tokio::{sync::watch, time::sleep};
+
+use crate::{chains::chains::L1Set, config::RetentionConfig};
+
+use super::db::DB;
+
+pub struct Pruner {
+    pub db: Arc<DB>,
+    pub retention: RetentionConfig,
+    /// Follows config reloads, heights must be posted on every current L1 to be pruned
+    pub l1s: watch::Receiver<L1Set>,
+}
+
+impl Pruner {
+    pub fn new(db: Arc<DB>, retention: RetentionConfig, l1s: watch::Receiver<L1Set>) -> Self {
+        Self { db, retention, l1s }
+    }
+
+    /// Applies the retention policy every `prune_interval` minutes
+    pub async fn run(&self) -> Result<()> {
+        tracing::info!(
+            "Pruner running. extra_proofs_days:{:?} rejected_submissions_days:{:?}",
+            self.retention.extra_proofs_days,
+            self.retention.rejected_submissions_days
+        );
+        loop {
+            let chains: Vec<String> = self.l1s.borrow().providers.keys().cloned().collect();
+            if let Err(e) = self.db.prune(&self.retention, &chains, false).await {
+                tracing::warn!("Failed to prune. error:{}", e);
+            }
+            sleep(Duration::from_secs(self.retention.prune_interval * 60)).await;
+        }
+    }
+}
@@ -2,11 +2,13 @@ use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Proofs by height, as stored before one document per block. Only read by the migration.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProofDetails {
     pub blocks: HashMap<String, BlockFields>,
 }
 
+/// Post status by height and chain, as stored before one document per block. Only read by the migration.
 #[derive(Serialize, Deserialize)]
 pub struct L1Details {
     pub l1s: HashMap<String, HashMap<String, bool>>,
@@ -19,10 +21,217 @@ pub struct BlockFields {
     pub timestamp: DateTime,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProverDetails {
+    /// Empty when the proof is in the blob store
     pub proof: String,
+    /// Hash of the proof in the blob store. None for proofs stored inline before blobs.
+    #[serde(default)]
+    pub proof_hash: Option<String>,
     pub proof_type: String,
     pub verified: bool,
     pub timestamp: DateTime,
 }
+
+/// Everything about one L2 height, one document per block in the blocks collection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockRecord {
+    #[serde(rename = "_id")]
+    pub height: u64,
+    pub threshold_verified: bool,
+    pub timestamp: DateTime,
+    pub submissions: Vec<Submission>,
+    pub settlements: Vec<Settlement>,
+}
+
+/// A verified proof from one prover. The proof itself is in the blob store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Submission {
+    pub identifier: String,
+    /// Only set for proofs stored inline before blobs
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub proof: String,
+    #[serde(default)]
+    pub proof_hash: Option<String>,
+    pub proof_type: String,
+    pub verified: bool,
+    pub timestamp: DateTime,
+}
+
+/// Post status of the block on one L1, with the transactions and attempts that settled it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Settlement {
+    pub chain: String,
+    pub posted: bool,
+    pub updated: DateTime,
+    /// Settlement attempts by the poster, failed ones included
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub first_attempt: Option<DateTime>,
+    /// Error of the last attempt, cleared once an attempt succeeds
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub commit: Option<SettlementTx>,
+    #[serde(default)]
+    pub finalize: Option<SettlementTx>,
+    /// When the transactions were seen final on the chain
+    #[serde(default)]
+    pub finalized_at: Option<DateTime>,
+}
+
+/// A commit or finalize transaction that landed on an L1.
+/// Amounts in wei are decimal strings, they do not fit in a BSON integer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SettlementTx {
+    pub tx_hash: String,
+    pub l1_block: u64,
+    pub gas_used: u64,
+    pub effective_gas_price: String,
+    pub fee: String,
+    /// Times the transaction was broadcast, fee bumped replacements included
+    pub broadcasts: u32,
+    pub landed_at: DateTime,
+}
+
+impl Submission {
+    pub fn new(identifier: &str, details: ProverDetails) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            proof: details.proof,
+            proof_hash: details.proof_hash,
+            proof_type: details.proof_type,
+            verified: details.verified,
+            timestamp: details.timestamp,
+        }
+    }
+
+    pub fn details(&self) -> ProverDetails {
+        ProverDetails {
+            proof: self.proof.clone(),
+            proof_hash: self.proof_hash.clone(),
+            proof_type: self.proof_type.clone(),
+            verified: self.verified,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl Settlement {
+    pub fn new(chain: &str) -> Self {
+        Self {
+            chain: chain.to_string(),
+            posted: false,
+            updated: DateTime::now(),
+            attempts: 0,
+            first_attempt: None,
+            last_error: None,
+            commit: None,
+            finalize: None,
+            finalized_at: None,
+        }
+    }
+
+    /// Counts an attempt. Transactions of earlier attempts are kept for steps that this one skipped.
+    pub fn record_attempt(
+        &mut self,
+        commit: Option<SettlementTx>,
+        finalize: Option<SettlementTx>,
+        error: Option<String>,
+    ) {
+        let now = DateTime::now();
+        self.attempts += 1;
+        self.first_attempt.get_or_insert(now);
+        self.last_error = error;
+        if commit.is_some() {
+            self.commit = commit;
+        }
+        if finalize.is_some() {
+            self.finalize = finalize;
+        }
+        self.updated = now;
+    }
+
+    pub fn set_posted(&mut self, posted: bool) {
+        let now = DateTime::now();
+        self.posted = posted;
+        self.finalized_at = posted.then_some(now);
+        self.updated = now;
+    }
+}
+
+/// What became of a `twarb_sendProof` call
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionOutcome {
+    /// Queued for the verifier, replaced once it decides
+    Received,
+    Accepted,
+    /// The prover already had a proof for the height
+    Duplicate,
+    /// The request or the proof could not be parsed
+    Malformed,
+    InvalidSender,
+    Unsupported,
+    VerificationFailed,
+    /// The proof could not be handled, e.g. the database was unavailable
+    Error,
+}
+
+/// One `twarb_sendProof` call in the append-only submission log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubmissionLog {
+    pub identifier: Option<String>,
+    pub remote_addr: Option<String>,
+    /// keccak256 of the request params as received
+    pub proof_hash: String,
+    /// Height the proof claims, read before it is verified
+    pub height: Option<u64>,
+    pub proof_type: Option<String>,
+    pub outcome: SubmissionOutcome,
+    pub error: Option<String>,
+    pub timestamp: DateTime,
+}
+
+impl SubmissionLog {
+    pub fn received(remote_addr: Option<String>, proof_hash: String) -> Self {
+        Self {
+            identifier: None,
+            remote_addr,
+            proof_hash,
+            height: None,
+            proof_type: None,
+            outcome: SubmissionOutcome::Received,
+            error: None,
+            timestamp: DateTime::now(),
+        }
+    }
+
+    pub fn with_identifier(mut self, identifier: &str) -> Self {
+        self.identifier = Some(identifier.to_string());
+        self
+    }
+
+    pub fn with_proof_type(mut self, proof_type: &str) -> Self {
+        self.proof_type = Some(proof_type.to_string());
+        self
+    }
+
+    pub fn with_height(mut self, height: u64) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    pub fn with_outcome(mut self, outcome: SubmissionOutcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    /// Sets an outcome other than accepted, with the reason for it
+    pub fn rejected(mut self, outcome: SubmissionOutcome, error: impl ToString) -> Self {
+        self.outcome = outcome;
+        self.error = Some(error.to_string());
+        self
+    }
+}
@@ -1,115 +1,543 @@
 use std::{
     collections::HashMap,
-    net::SocketAddr,
-    path::Path,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
-use anyhow::{Result, Error};
+use anyhow::{Error, Result};
+use mongodb::bson::DateTime;
+use rusqlite::{params, Connection, OptionalExtension};
 
-use clap::builder::Str;
-use rusqlite::{params, Connection};
-use tokio::sync::mpsc::Sender;
+use super::{
+    schema::{ProverDetails, Settlement, SettlementTx, SubmissionLog, SubmissionOutcome},
+    storage::{ProofInsert, Storage, SubmissionQuery},
+};
 
-use crate::config::{L1Details, ProverDetails};
+static PROOFS_TABLE: &str = "proofs";
+static BLOCKS_TABLE: &str = "blocks";
+static POST_STATUS_TABLE: &str = "post_status";
+static SUBMISSION_LOG_TABLE: &str = "submission_log";
+static IN_MEMORY_PATH: &str = ":memory:";
 
-pub struct DB {
-    poster_tx: Sender<bool>,
+/// SQLite backend, for small deployments and CI. Queries are short and run on the calling task.
+#[derive(Clone)]
+pub struct SqliteStore {
     conn: Arc<Mutex<Connection>>,
+    path: String,
 }
 
-static MAIN_TABLE: &str = "twine_arbitrager_main";
-static PROOFS_TABLE_SUFFIX: &str = "_proofs";
-static POSTER_TABLE_SUFFIX: &str = "_poster";
-
-impl DB {
-    pub fn new(
-        poster_tx: Sender<bool>,
-        path: String,
-        provers: Vec<String>,
-        l1s: Vec<String>,
-    ) -> Self {
-        let p = Path::new(&path);
-
-        let conn = Connection::open(p).expect("Failed connecting to db");
-
-        // Main table
-        let create_table_query = format!(
-            "CREATE TABLE IF NOT EXISTS {} (
-                id INTEGER PRIMARY KEY,
-                block_number INTEGER NOT NULL UNIQUE
-            )",
-            MAIN_TABLE
-        );
-        conn.execute(&create_table_query, [])
-            .expect("Failed creating main table");
-
-        // Provers Table
-        for k in provers.iter() {
-            let table_name = format!("{}{}", k, PROOFS_TABLE_SUFFIX);
-            let query = format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    block INTEGER PRIMARY KEY,
-                    proof TEXT,
-                    verified BOOLEAN DEFAULT FALSE,
-                    FOREIGN KEY (block) REFERENCES {}(block_number) ON DELETE CASCADE
-                )",
-                table_name, MAIN_TABLE
+impl SqliteStore {
+    /// Opens or creates the database file. `:memory:` keeps everything in memory.
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {PROOFS_TABLE} (
+                block INTEGER NOT NULL,
+                identifier TEXT NOT NULL,
+                proof_type TEXT NOT NULL,
+                proof TEXT NOT NULL,
+                verified BOOLEAN NOT NULL,
+                timestamp INTEGER NOT NULL,
+                PRIMARY KEY (block, identifier)
+            );
+            CREATE TABLE IF NOT EXISTS {BLOCKS_TABLE} (
+                block INTEGER PRIMARY KEY,
+                threshold_verified BOOLEAN NOT NULL DEFAULT FALSE,
+                timestamp INTEGER NOT NULL
             );
+            CREATE TABLE IF NOT EXISTS {POST_STATUS_TABLE} (
+                block INTEGER NOT NULL,
+                chain TEXT NOT NULL,
+                posted BOOLEAN NOT NULL,
+                settlement TEXT,
+                PRIMARY KEY (block, chain)
+            );
+            CREATE TABLE IF NOT EXISTS {SUBMISSION_LOG_TABLE} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                identifier TEXT,
+                remote_addr TEXT,
+                proof_hash TEXT NOT NULL,
+                height INTEGER,
+                proof_type TEXT,
+                outcome TEXT NOT NULL,
+                error TEXT,
+                timestamp INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {SUBMISSION_LOG_TABLE}_identifier
+                ON {SUBMISSION_LOG_TABLE} (identifier);
+            CREATE INDEX IF NOT EXISTS {SUBMISSION_LOG_TABLE}_height
+                ON {SUBMISSION_LOG_TABLE} (height);"
+        ))?;
+        // files created before settlement details and proof blobs
+        add_column(&conn, POST_STATUS_TABLE, "settlement", "TEXT")?;
+        add_column(&conn, PROOFS_TABLE, "proof_hash", "TEXT")?;
+        tracing::info!("SQLite database loaded. path:{}", path);
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            path: path.to_string(),
+        })
+    }
+
+    /// Directory for proof blobs next to the database file. None for an in-memory database.
+    pub fn blob_dir(&self) -> Option<PathBuf> {
+        (self.path != IN_MEMORY_PATH).then(|| PathBuf::from(format!("{}.blobs", self.path)))
+    }
+
+    /// Reads, changes and writes back the settlement of the block on the chain, stored as JSON
+    /// next to its post status
+    fn modify_settlement(
+        &self,
+        chain: &str,
+        block: u64,
+        modify: impl FnOnce(&mut Settlement),
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let stored = conn
+            .query_row(
+                &format!(
+                    "SELECT posted, settlement FROM {POST_STATUS_TABLE}
+                    WHERE block = ?1 AND chain = ?2"
+                ),
+                params![block, chain],
+                |row| Ok((row.get::<_, bool>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()?;
+        let mut settlement = match stored {
+            Some((_, Some(json))) => serde_json::from_str(&json)?,
+            Some((posted, None)) => Settlement {
+                posted,
+                ..Settlement::new(chain)
+            },
+            None => Settlement::new(chain),
+        };
+        modify(&mut settlement);
 
-            conn.execute(&query, [])
-                .expect("Failed creating proofs table");
+        conn.execute(
+            &format!(
+                "INSERT INTO {POST_STATUS_TABLE} (block, chain, posted, settlement)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (block, chain) DO UPDATE
+                SET posted = excluded.posted, settlement = excluded.settlement"
+            ),
+            params![
+                block,
+                chain,
+                settlement.posted,
+                serde_json::to_string(&settlement)?
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// Adds the column unless the table already has it
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        &format!("SELECT COUNT(*) > 0 FROM pragma_table_info('{table}') WHERE name = ?1"),
+        [column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStore {
+    async fn add_proof(
+        &self,
+        block: u64,
+        identifier: &str,
+        details: ProverDetails,
+    ) -> Result<ProofInsert> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let inserted = tx.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {PROOFS_TABLE}
+                    (block, identifier, proof_type, proof, proof_hash, verified, timestamp)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            ),
+            params![
+                block,
+                identifier,
+                details.proof_type,
+                details.proof,
+                details.proof_hash,
+                details.verified,
+                details.timestamp.timestamp_millis()
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(ProofInsert::Duplicate);
         }
 
-        // Posters Table
-        for k in l1s.iter() {
-            let table_name = format!("{}{}", k, POSTER_TABLE_SUFFIX);
-            let query = format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    block INTEGER PRIMARY KEY,
-                    posted BOOLEAN DEFAULT FALSE,
-                    FOREIGN KEY (block) REFERENCES {}(block_number) ON DELETE CASCADE
-                )",
-                table_name, MAIN_TABLE
-            );
+        tx.execute(
+            &format!(
+                "INSERT INTO {BLOCKS_TABLE} (block, timestamp) VALUES (?1, ?2)
+                ON CONFLICT (block) DO UPDATE SET timestamp = excluded.timestamp"
+            ),
+            params![block, DateTime::now().timestamp_millis()],
+        )?;
+        let provers: usize = tx.query_row(
+            &format!("SELECT COUNT(*) FROM {PROOFS_TABLE} WHERE block = ?1"),
+            [block],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
 
-            conn.execute(&query, [])
-                .expect("Failed creating poster table");
+        Ok(ProofInsert::Added { provers })
+    }
+
+    async fn claim_threshold(&self, block: u64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            &format!(
+                "UPDATE {BLOCKS_TABLE} SET threshold_verified = TRUE
+                WHERE block = ?1 AND NOT threshold_verified"
+            ),
+            [block],
+        )?;
+        Ok(updated == 1)
+    }
+
+    async fn oldest_proof(&self, block: u64) -> Result<ProverDetails> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT proof, proof_hash, proof_type, verified, timestamp FROM {PROOFS_TABLE}
+                WHERE block = ?1 ORDER BY timestamp ASC LIMIT 1"
+            ),
+            [block],
+            |row| {
+                Ok(ProverDetails {
+                    proof: row.get(0)?,
+                    proof_hash: row.get(1)?,
+                    proof_type: row.get(2)?,
+                    verified: row.get(3)?,
+                    timestamp: DateTime::from_millis(row.get(4)?),
+                })
+            },
+        )
+        .optional()?
+        .ok_or_else(|| Error::msg("Failed to fetch proofs"))
+    }
+
+    async fn proofs(&self, block: u64) -> Result<Vec<(String, ProverDetails)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT identifier, proof, proof_hash, proof_type, verified, timestamp
+            FROM {PROOFS_TABLE} WHERE block = ?1"
+        ))?;
+        let proofs = statement
+            .query_map([block], |row| {
+                Ok((
+                    row.get(0)?,
+                    ProverDetails {
+                        proof: row.get(1)?,
+                        proof_hash: row.get(2)?,
+                        proof_type: row.get(3)?,
+                        verified: row.get(4)?,
+                        timestamp: DateTime::from_millis(row.get(5)?),
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(proofs)
+    }
+
+    async fn remove_proofs(&self, block: u64, identifiers: &[String]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for identifier in identifiers {
+            tx.execute(
+                &format!("DELETE FROM {PROOFS_TABLE} WHERE block = ?1 AND identifier = ?2"),
+                params![block, identifier],
+            )?;
         }
+        tx.commit()?;
+        Ok(())
+    }
 
-        tracing::info!("Database loaded!");
+    async fn threshold_verified_blocks(&self) -> Result<Vec<u64>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT block FROM {BLOCKS_TABLE} WHERE threshold_verified"
+        ))?;
+        let blocks = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<u64>>>()?;
+        Ok(blocks)
+    }
 
-        Self {
-            poster_tx,
-            conn: Arc::new(Mutex::new(conn)),
+    async fn heights(&self, from: u64, to: u64) -> Result<Vec<u64>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT block FROM (
+                SELECT block FROM {BLOCKS_TABLE}
+                UNION SELECT block FROM {PROOFS_TABLE}
+                UNION SELECT block FROM {POST_STATUS_TABLE}
+            ) WHERE block BETWEEN ?1 AND ?2 ORDER BY block"
+        ))?;
+        let blocks = statement
+            .query_map(params![from, to], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<u64>>>()?;
+        Ok(blocks)
+    }
+
+    async fn set_post_status(&self, chain: &str, block: u64, posted: bool) -> Result<()> {
+        self.modify_settlement(chain, block, |settlement| settlement.set_posted(posted))
+    }
+
+    async fn record_attempt(
+        &self,
+        chain: &str,
+        block: u64,
+        commit: Option<SettlementTx>,
+        finalize: Option<SettlementTx>,
+        error: Option<String>,
+    ) -> Result<()> {
+        self.modify_settlement(chain, block, |settlement| {
+            settlement.record_attempt(commit, finalize, error)
+        })
+    }
+
+    async fn settlements(&self, block: u64) -> Result<Vec<Settlement>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT chain, posted, settlement FROM {POST_STATUS_TABLE} WHERE block = ?1"
+        ))?;
+        let rows = statement.query_map([block], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, bool>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let mut settlements = vec![];
+        for row in rows {
+            let settlement = match row? {
+                (_, _, Some(json)) => serde_json::from_str(&json)?,
+                (chain, posted, None) => Settlement {
+                    posted,
+                    ..Settlement::new(&chain)
+                },
+            };
+            settlements.push(settlement);
         }
+        Ok(settlements)
+    }
+
+    async fn restore_settlement(&self, block: u64, settlement: Settlement) -> Result<()> {
+        let chain = settlement.chain.clone();
+        self.modify_settlement(&chain, block, |stored| *stored = settlement)
     }
 
-    pub fn save_proof_to_db(&self, identifier: String, block: u64, proof: String) -> Result<()> {
+    async fn post_statuses(&self) -> Result<HashMap<u64, HashMap<String, bool>>> {
         let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT block, chain, posted FROM {POST_STATUS_TABLE}"
+        ))?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        })?;
 
-        // Insert Block Number into main table
-        let query = format!(
-            "INSERT INTO {}{} (block_number) VALUES (?1)",
-            identifier, MAIN_TABLE 
-        );
+        let mut statuses: HashMap<u64, HashMap<String, bool>> = HashMap::new();
+        for row in rows {
+            let (block, chain, posted) = row?;
+            statuses.entry(block).or_default().insert(chain, posted);
+        }
+        Ok(statuses)
+    }
+
+    async fn log_submission(&self, entry: SubmissionLog) -> Result<()> {
+        let outcome = serde_json::to_value(entry.outcome)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT INTO {SUBMISSION_LOG_TABLE}
+                    (identifier, remote_addr, proof_hash, height, proof_type, outcome, error, timestamp)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+            ),
+            params![
+                entry.identifier,
+                entry.remote_addr,
+                entry.proof_hash,
+                entry.height,
+                entry.proof_type,
+                outcome.as_str(),
+                entry.error,
+                entry.timestamp.timestamp_millis()
+            ],
+        )?;
+        Ok(())
+    }
 
-        conn.execute(&query, [block])?;
+    async fn submissions(&self, query: SubmissionQuery) -> Result<Vec<SubmissionLog>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT identifier, remote_addr, proof_hash, height, proof_type, outcome, error, timestamp
+            FROM {SUBMISSION_LOG_TABLE}
+            WHERE (?1 IS NULL OR identifier = ?1) AND (?2 IS NULL OR height = ?2)
+            ORDER BY id DESC LIMIT ?3"
+        ))?;
+        let rows = statement.query_map(
+            params![query.identifier, query.height, query.limit as i64],
+            |row| {
+                Ok((
+                    SubmissionLog {
+                        identifier: row.get(0)?,
+                        remote_addr: row.get(1)?,
+                        proof_hash: row.get(2)?,
+                        height: row.get(3)?,
+                        proof_type: row.get(4)?,
+                        outcome: SubmissionOutcome::Received,
+                        error: row.get(6)?,
+                        timestamp: DateTime::from_millis(row.get(7)?),
+                    },
+                    row.get::<_, String>(5)?,
+                ))
+            },
+        )?;
 
-        // Insert Proof into proofs table
-        let query = format!(
-            "INSERT INTO {}{} (batch, proof, verified) VALUES (?1, ?2, ?3)",
-            identifier, MAIN_TABLE 
-        );
-        conn.execute(&query, params![block, proof, true]).expect("Failed to execute query");
+        let mut entries = vec![];
+        for row in rows {
+            let (mut entry, outcome) = row?;
+            entry.outcome = serde_json::from_value(serde_json::Value::String(outcome))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    async fn prune_submission_log(&self, before: DateTime, dry_run: bool) -> Result<u64> {
+        let accepted = serde_json::to_value(SubmissionOutcome::Accepted)?;
+        let filter = format!("FROM {SUBMISSION_LOG_TABLE} WHERE outcome != ?1 AND timestamp < ?2");
+        let conn = self.conn.lock().unwrap();
+        let args = params![accepted.as_str(), before.timestamp_millis()];
+        if dry_run {
+            let count: u64 =
+                conn.query_row(&format!("SELECT COUNT(*) {filter}"), args, |row| row.get(0))?;
+            return Ok(count);
+        }
+        Ok(conn.execute(&format!("DELETE {filter}"), args)? as u64)
+    }
 
-        
+    async fn delete_blocks(&self, heights: &[u64]) -> Result<u64> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut deleted = 0;
+        for height in heights {
+            let mut rows = 0;
+            for table in [PROOFS_TABLE, BLOCKS_TABLE, POST_STATUS_TABLE] {
+                rows += tx.execute(&format!("DELETE FROM {table} WHERE block = ?1"), [height])?;
+            }
+            deleted += (rows > 0) as u64;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    async fn delete_submissions(&self, range: Option<(u64, u64)>) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = match range {
+            Some((from, to)) => conn.execute(
+                &format!("DELETE FROM {SUBMISSION_LOG_TABLE} WHERE height BETWEEN ?1 AND ?2"),
+                params![from, to],
+            )?,
+            None => conn.execute(&format!("DELETE FROM {SUBMISSION_LOG_TABLE}"), [])?,
+        };
+        Ok(deleted as u64)
+    }
 
+    /// Drops every table, the submission log included
+    async fn delete_all(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch(&format!(
+            "DROP TABLE IF EXISTS {PROOFS_TABLE};
+            DROP TABLE IF EXISTS {BLOCKS_TABLE};
+            DROP TABLE IF EXISTS {POST_STATUS_TABLE};
+            DROP TABLE IF EXISTS {SUBMISSION_LOG_TABLE};"
+        ))?;
         Ok(())
     }
 }
 
-// Mutex needed
-pub fn save_to_db(block: u64, prover_address: SocketAddr, proof: Vec<u8>) {
-    panic!("todo: implement this");
+#[cfg(test)]
+mod test {
+    use mongodb::bson::DateTime;
+
+    use super::SqliteStore;
+    use crate::database::{
+        schema::ProverDetails,
+        storage::{ProofInsert, Storage, SubmissionQuery},
+    };
+
+    fn proof(proof: &str, millis: i64) -> ProverDetails {
+        ProverDetails {
+            proof: proof.to_string(),
+            proof_hash: None,
+            proof_type: "dummy".to_string(),
+            verified: true,
+            timestamp: DateTime::from_millis(millis),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let store = SqliteStore::open(":memory:").unwrap();
+
+        assert_eq!(
+            store
+                .add_proof(7, "prover-b", proof("bb", 2))
+                .await
+                .unwrap(),
+            ProofInsert::Added { provers: 1 }
+        );
+        assert_eq!(
+            store
+                .add_proof(7, "prover-b", proof("bb", 3))
+                .await
+                .unwrap(),
+            ProofInsert::Duplicate
+        );
+        store
+            .add_proof(7, "prover-a", proof("aa", 1))
+            .await
+            .unwrap();
+        assert!(store.claim_threshold(7).await.unwrap());
+        assert!(!store.claim_threshold(7).await.unwrap());
+        assert!(!store.claim_threshold(8).await.unwrap());
+        assert_eq!(
+            store
+                .add_proof(7, "prover-c", proof("cc", 4))
+                .await
+                .unwrap(),
+            ProofInsert::Added { provers: 3 }
+        );
+
+        assert_eq!(store.oldest_proof(7).await.unwrap().proof, "aa");
+        assert!(store.oldest_proof(8).await.is_err());
+        assert_eq!(store.threshold_verified_blocks().await.unwrap(), vec![7]);
+
+        store.set_post_status("ethereum", 7, false).await.unwrap();
+        store
+            .record_attempt("ethereum", 7, None, None, Some("reverted".to_string()))
+            .await
+            .unwrap();
+        store.set_post_status("ethereum", 7, true).await.unwrap();
+        let statuses = store.post_statuses().await.unwrap();
+        assert!(statuses[&7]["ethereum"]);
+
+        let settlements = store.settlements(7).await.unwrap();
+        assert_eq!(settlements[0].attempts, 1);
+        assert_eq!(settlements[0].last_error.as_deref(), Some("reverted"));
+    }
 }
@@ -0,0 +1,377 @@
+use std::{collections::HashMap, future::Future};
+
+use anyhow::{Error, Result};
+use mongodb::bson::DateTime;
+use serde::Deserialize;
+
+use super::{
+    memory::MemoryStore,
+    mongo::MongoStore,
+    schema::{ProverDetails, Settlement, SettlementTx, SubmissionLog},
+    sql::SqliteStore,
+};
+
+static SQLITE_SCHEME: &str = "sqlite://";
+static MEMORY_SCHEME: &str = "memory://";
+static MONGO_SCHEMES: [&str; 2] = ["mongodb://", "mongodb+srv://"];
+static DEFAULT_SUBMISSION_LIMIT: usize = 100;
+
+/// Outcome of adding a prover's proof for a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofInsert {
+    /// The prover already submitted a proof for the block
+    Duplicate,
+    Added {
+        /// Provers with a proof for the block, the new one included
+        provers: usize,
+    },
+}
+
+/// Filter for the submission log. Entries are returned latest first.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubmissionQuery {
+    pub identifier: Option<String>,
+    pub height: Option<u64>,
+    #[serde(default = "default_submission_limit")]
+    pub limit: usize,
+}
+
+fn default_submission_limit() -> usize {
+    DEFAULT_SUBMISSION_LIMIT
+}
+
+impl SubmissionQuery {
+    pub fn matches(&self, entry: &SubmissionLog) -> bool {
+        self.identifier.as_ref().map_or(true, |identifier| {
+            entry.identifier.as_ref() == Some(identifier)
+        }) && self
+            .height
+            .map_or(true, |height| entry.height == Some(height))
+    }
+}
+
+/// What the aggregator keeps about proofs and their settlement, independent of the database
+pub trait Storage {
+    fn add_proof(
+        &self,
+        block: u64,
+        identifier: &str,
+        details: ProverDetails,
+    ) -> impl Future<Output = Result<ProofInsert>> + Send;
+
+    /// Marks the block threshold verified. Returns true only for the one caller that changed it,
+    /// however many workers or instances race for the same block.
+    fn claim_threshold(&self, block: u64) -> impl Future<Output = Result<bool>> + Send;
+
+    /// The first proof that was submitted for the block
+    fn oldest_proof(&self, block: u64) -> impl Future<Output = Result<ProverDetails>> + Send;
+
+    /// Every proof of the block, by prover
+    fn proofs(
+        &self,
+        block: u64,
+    ) -> impl Future<Output = Result<Vec<(String, ProverDetails)>>> + Send;
+
+    /// Removes the proofs of the given provers from the block
+    fn remove_proofs(
+        &self,
+        block: u64,
+        identifiers: &[String],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn threshold_verified_blocks(&self) -> impl Future<Output = Result<Vec<u64>>> + Send;
+
+    /// Heights in `from..=to` with a proof or a settlement, in order
+    fn heights(&self, from: u64, to: u64) -> impl Future<Output = Result<Vec<u64>>> + Send;
+
+    fn set_post_status(
+        &self,
+        chain: &str,
+        block: u64,
+        posted: bool,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Counts a settlement attempt of the block on the chain, with the transactions that landed
+    /// or the error it failed with
+    fn record_attempt(
+        &self,
+        chain: &str,
+        block: u64,
+        commit: Option<SettlementTx>,
+        finalize: Option<SettlementTx>,
+        error: Option<String>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Settlement details of the block on every chain it was posted to
+    fn settlements(&self, block: u64) -> impl Future<Output = Result<Vec<Settlement>>> + Send;
+
+    /// Replaces the settlement of the block on its chain, when restoring a backup
+    fn restore_settlement(
+        &self,
+        block: u64,
+        settlement: Settlement,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Post status of every block, by chain
+    fn post_statuses(
+        &self,
+    ) -> impl Future<Output = Result<HashMap<u64, HashMap<String, bool>>>> + Send;
+
+    /// Appends an entry to the submission log. Entries are never changed afterwards.
+    fn log_submission(&self, entry: SubmissionLog) -> impl Future<Output = Result<()>> + Send;
+
+    fn submissions(
+        &self,
+        query: SubmissionQuery,
+    ) -> impl Future<Output = Result<Vec<SubmissionLog>>> + Send;
+
+    /// Submission log entries that were not accepted and are older than `before`.
+    /// They are deleted unless `dry_run` is set. Returns how many there are.
+    fn prune_submission_log(
+        &self,
+        before: DateTime,
+        dry_run: bool,
+    ) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Removes the blocks with their proofs, threshold state and settlements. Returns how many
+    /// were stored.
+    fn delete_blocks(&self, heights: &[u64]) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Removes submission log entries with a height in `from..=to`, or every entry without a range
+    fn delete_submissions(
+        &self,
+        range: Option<(u64, u64)>,
+    ) -> impl Future<Output = Result<u64>> + Send;
+
+    fn delete_all(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+pub enum Store {
+    Mongo(MongoStore),
+    Sqlite(SqliteStore),
+    /// Nothing is kept after the process exits
+    Memory(MemoryStore),
+}
+
+impl Store {
+    /// Opens the backend named by the scheme of `db_path`: `mongodb://`, `mongodb+srv://`,
+    /// `sqlite://<file>` or `memory://`
+    pub async fn open(db_path: &str) -> Result<Store> {
+        if db_path.starts_with(MEMORY_SCHEME) {
+            tracing::warn!("Using in-memory storage, nothing is kept after a restart");
+            return Ok(Store::Memory(MemoryStore::default()));
+        }
+        if let Some(path) = db_path.strip_prefix(SQLITE_SCHEME) {
+            return Ok(Store::Sqlite(SqliteStore::open(path)?));
+        }
+        if MONGO_SCHEMES
+            .iter()
+            .any(|scheme| db_path.starts_with(scheme))
+        {
+            return Ok(Store::Mongo(MongoStore::connect(db_path).await?));
+        }
+        Err(Error::msg(
+            "db_path must start with mongodb://, mongodb+srv://, sqlite:// or memory://",
+        ))
+    }
+}
+
+/// Whether `db_path` names a supported backend, without connecting to it
+pub fn is_valid_db_path(db_path: &str) -> bool {
+    db_path.starts_with(SQLITE_SCHEME)
+        || db_path.starts_with(MEMORY_SCHEME)
+        || MONGO_SCHEMES
+            .iter()
+            .any(|scheme| db_path.starts_with(scheme))
+}
+
+/// Name to confirm destructive commands with: the MongoDB host, the SQLite file or `memory`
+pub fn database_name(db_path: &str) -> String {
+    if db_path.starts_with(MEMORY_SCHEME) {
+        return "memory".to_string();
+    }
+    if let Some(path) = db_path.strip_prefix(SQLITE_SCHEME) {
+        return path.to_string();
+    }
+    let address = MONGO_SCHEMES
+        .iter()
+        .find_map(|scheme| db_path.strip_prefix(scheme))
+        .unwrap_or(db_path);
+    // credentials never end up in the name
+    let address = address.rsplit_once('@').map_or(address, |(_, host)| host);
+    address
+        .split(['/', '?'])
+        .next()
+        .unwrap_or(address)
+        .to_string()
+}
+
+impl Storage for Store {
+    async fn add_proof(
+        &self,
+        block: u64,
+        identifier: &str,
+        details: ProverDetails,
+    ) -> Result<ProofInsert> {
+        match self {
+            Store::Mongo(mongo) => mongo.add_proof(block, identifier, details).await,
+            Store::Sqlite(sqlite) => sqlite.add_proof(block, identifier, details).await,
+            Store::Memory(memory) => memory.add_proof(block, identifier, details).await,
+        }
+    }
+
+    async fn claim_threshold(&self, block: u64) -> Result<bool> {
+        match self {
+            Store::Mongo(mongo) => mongo.claim_threshold(block).await,
+            Store::Sqlite(sqlite) => sqlite.claim_threshold(block).await,
+            Store::Memory(memory) => memory.claim_threshold(block).await,
+        }
+    }
+
+    async fn oldest_proof(&self, block: u64) -> Result<ProverDetails> {
+        match self {
+            Store::Mongo(mongo) => mongo.oldest_proof(block).await,
+            Store::Sqlite(sqlite) => sqlite.oldest_proof(block).await,
+            Store::Memory(memory) => memory.oldest_proof(block).await,
+        }
+    }
+
+    async fn proofs(&self, block: u64) -> Result<Vec<(String, ProverDetails)>> {
+        match self {
+            Store::Mongo(mongo) => mongo.proofs(block).await,
+            Store::Sqlite(sqlite) => sqlite.proofs(block).await,
+            Store::Memory(memory) => memory.proofs(block).await,
+        }
+    }
+
+    async fn remove_proofs(&self, block: u64, identifiers: &[String]) -> Result<()> {
+        match self {
+            Store::Mongo(mongo) => mongo.remove_proofs(block, identifiers).await,
+            Store::Sqlite(sqlite) => sqlite.remove_proofs(block, identifiers).await,
+            Store::Memory(memory) => memory.remove_proofs(block, identifiers).await,
+        }
+    }
+
+    async fn threshold_verified_blocks(&self) -> Result<Vec<u64>> {
+        match self {
+            Store::Mongo(mongo) => mongo.threshold_verified_blocks().await,
+            Store::Sqlite(sqlite) => sqlite.threshold_verified_blocks().await,
+            Store::Memory(memory) => memory.threshold_verified_blocks().await,
+        }
+    }
+
+    async fn heights(&self, from: u64, to: u64) -> Result<Vec<u64>> {
+        match self {
+            Store::Mongo(mongo) => mongo.heights(from, to).await,
+            Store::Sqlite(sqlite) => sqlite.heights(from, to).await,
+            Store::Memory(memory) => memory.heights(from, to).await,
+        }
+    }
+
+    async fn set_post_status(&self, chain: &str, block: u64, posted: bool) -> Result<()> {
+        match self {
+            Store::Mongo(mongo) => mongo.set_post_status(chain, block, posted).await,
+            Store::Sqlite(sqlite) => sqlite.set_post_status(chain, block, posted).await,
+            Store::Memory(memory) => memory.set_post_status(chain, block, posted).await,
+        }
+    }
+
+    async fn record_attempt(
+        &self,
+        chain: &str,
+        block: u64,
+        commit: Option<SettlementTx>,
+        finalize: Option<SettlementTx>,
+        error: Option<String>,
+    ) -> Result<()> {
+        match self {
+            Store::Mongo(mongo) => {
+                mongo
+                    .record_attempt(chain, block, commit, finalize, error)
+                    .await
+            }
+            Store::Sqlite(sqlite) => {
+                sqlite
+                    .record_attempt(chain, block, commit, finalize, error)
+                    .await
+            }
+            Store::Memory(memory) => {
+                memory
+                    .record_attempt(chain, block, commit, finalize, error)
+                    .await
+            }
+        }
+    }
+
+    async fn settlements(&self, block: u64) -> Result<Vec<Settlement>> {
+        match self {
+            Store::Mongo(mongo) => mongo.settlements(block).await,
+            Store::Sqlite(sqlite) => sqlite.settlements(block).await,
+            Store::Memory(memory) => memory.settlements(block).await,
+        }
+    }
+
+    async fn restore_settlement(&self, block: u64, settlement: Settlement) -> Result<()> {
+        match self {
+            Store::Mongo(mongo) => mongo.restore_settlement(block, settlement).await,
+            Store::Sqlite(sqlite) => sqlite.restore_settlement(block, settlement).await,
+            Store::Memory(memory) => memory.restore_settlement(block, settlement).await,
+        }
+    }
+
+    async fn post_statuses(&self) -> Result<HashMap<u64, HashMap<String, bool>>> {
+        match self {
+            Store::Mongo(mongo) => mongo.post_statuses().await,
+            Store::Sqlite(sqlite) => sqlite.post_statuses().await,
+            Store::Memory(memory) => memory.post_statuses().await,
+        }
+    }
+
+    async fn log_submission(&self, entry: SubmissionLog) -> Result<()> {
+        match self {
+            Store::Mongo(mongo) => mongo.log_submission(entry).await,
+            Store::Sqlite(sqlite) => sqlite.log_submission(entry).await,
+            Store::Memory(memory) => memory.log_submission(entry).await,
+        }
+    }
+
+    async fn submissions(&self, query: SubmissionQuery) -> Result<Vec<SubmissionLog>> {
+        match self {
+            Store::Mongo(mongo) => mongo.submissions(query).await,
+            Store::Sqlite(sqlite) => sqlite.submissions(query).await,
+            Store::Memory(memory) => memory.submissions(query).await,
+        }
+    }
+
+    async fn prune_submission_log(&self, before: DateTime, dry_run: bool) -> Result<u64> {
+        match self {
+            Store::Mongo(mongo) => mongo.prune_submission_log(before, dry_run).await,
+            Store::Sqlite(sqlite) => sqlite.prune_submission_log(before, dry_run).await,
+            Store::Memory(memory) => memory.prune_submission_log(before, dry_run).await,
+        }
+    }
+
+    async fn delete_blocks(&self, heights: &[u64]) -> Result<u64> {
+        match self {
+            Store::Mongo(mongo) => mongo.delete_blocks(heights).await,
+            Store::Sqlite(sqlite) => sqlite.delete_blocks(heights).await,
+            Store::Memory(memory) => memory.delete_blocks(heights).await,
+        }
+    }
+
+    async fn delete_submissions(&self, range: Option<(u64, u64)>) -> Result<u64> {
+        match self {
+            Store::Mongo(mongo) => mongo.delete_submissions(range).await,
+            Store::Sqlite(sqlite) => sqlite.delete_submissions(range).await,
+            Store::Memory(memory) => memory.delete_submissions(range).await,
+        }
+    }
+
+    async fn delete_all(&self) -> Result<()> {
+        match self {
+            Store::Mongo(mongo) => mongo.delete_all().await,
+            Store::Sqlite(sqlite) => sqlite.delete_all().await,
+            Store::Memory(memory) => memory.delete_all().await,
+        }
+    }
+}
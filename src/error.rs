@@ -9,9 +9,49 @@ pub enum AggregatorError {
     DBError(String),
     SubmitTransactionFailed(String),
     PosterError(String),
+    InvalidProof(u64),
+    WrongBatchNumber { expected: u64, provided: u64 },
+    BatchAlreadyCommitted(u64),
+    BatchAlreadyFinalized(u64),
+    BatchNotCommitted(u64),
+    TransactionReverted(String),
     Custom(String),
 }
 
+/// What the poster should do after a settlement transaction fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostAction {
+    /// Transient failure, try again later
+    Retry,
+    /// The step is already done on the contract
+    Skip,
+    /// Retrying will not help, an operator has to look at it
+    Alert,
+}
+
+impl AggregatorError {
+    pub fn post_action(&self) -> PostAction {
+        match self {
+            AggregatorError::BatchAlreadyCommitted(_)
+            | AggregatorError::BatchAlreadyFinalized(_) => PostAction::Skip,
+            AggregatorError::WrongBatchNumber { .. } | AggregatorError::BatchNotCommitted(_) => {
+                PostAction::Retry
+            }
+            AggregatorError::InvalidProof(_) | AggregatorError::TransactionReverted(_) => {
+                PostAction::Alert
+            }
+            _ => PostAction::Retry,
+        }
+    }
+}
+
+/// Action for any error returned while posting. Errors that are not aggregator errors,
+/// e.g. transport failures, are retried.
+pub fn post_action(e: &anyhow::Error) -> PostAction {
+    e.downcast_ref::<AggregatorError>()
+        .map_or(PostAction::Retry, |e| e.post_action())
+}
+
 impl std::error::Error for AggregatorError {}
 
 impl std::fmt::Display for AggregatorError {
@@ -27,6 +67,19 @@ impl std::fmt::Display for AggregatorError {
             AggregatorError::SubmitTransactionFailed(e) => write!(f, "{e:?}"),
             AggregatorError::DBError(e) => write!(f, "{e:?}"),
             AggregatorError::PosterError(e) => write!(f, "{e:?}"),
+            AggregatorError::InvalidProof(batch) => write!(f, "Invalid proof for batch {batch}"),
+            AggregatorError::WrongBatchNumber { expected, provided } => write!(
+                f,
+                "Wrong batch number: contract expects {expected}, got {provided}"
+            ),
+            AggregatorError::BatchAlreadyCommitted(batch) => {
+                write!(f, "Batch {batch} already committed")
+            }
+            AggregatorError::BatchAlreadyFinalized(batch) => {
+                write!(f, "Batch {batch} already finalized")
+            }
+            AggregatorError::BatchNotCommitted(batch) => write!(f, "Batch {batch} not committed"),
+            AggregatorError::TransactionReverted(e) => write!(f, "Transaction reverted: {e}"),
         }
     }
 }
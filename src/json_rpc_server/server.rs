@@ -1,11 +1,34 @@
+use alloy_primitives::keccak256;
 use anyhow::Result;
-use jsonrpsee::{server::Server, RpcModule};
+use jsonrpsee::{
+    server::{serve_with_graceful_shutdown, stop_channel, Server},
+    types::{ErrorObjectOwned, Params},
+    Methods, RpcModule,
+};
 use serde::{Deserialize, Serialize};
 use sp1_sdk::SP1ProofWithPublicValues;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::mpsc::Sender;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc::Sender, oneshot, watch},
+};
+use tower::Service;
 
-use crate::{error::AggregatorError, json_rpc_server::ServerReturnType, types::ProofType};
+use crate::{
+    aggregator::{reload::ReloadRequest, supervisor::Health},
+    database::{
+        db::DB,
+        schema::{SubmissionLog, SubmissionOutcome},
+        storage::{Storage, SubmissionQuery},
+    },
+    error::AggregatorError,
+    json_rpc_server::ServerReturnType,
+    types::{ProofType, SupportedProvers},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -35,46 +58,77 @@ enum ProofTypes {
 #[derive(Clone)]
 pub struct JsonRpcServer {
     // valid_senders: Arc<HashMap<SocketAddr, String>>,
-    valid_senders: Arc<HashMap<String, String>>,
-    verifier_tx: Sender<ProofType>,
+    /// Prover identifiers allowed to submit proofs. Updated in place when the config is reloaded.
+    valid_senders: Arc<RwLock<HashMap<String, String>>>,
+    verifier_tx: Sender<(ProofType, SubmissionLog)>,
+    reload_tx: Sender<ReloadRequest>,
+    health: Health,
+    /// Submissions refused here are logged directly, the verifier logs the rest
+    db: Arc<DB>,
 }
 
 impl JsonRpcServer {
-    pub fn new(addresses: HashMap<String, String>, verifier_tx: Sender<ProofType>) -> Self {
+    pub fn new(
+        addresses: Arc<RwLock<HashMap<String, String>>>,
+        verifier_tx: Sender<(ProofType, SubmissionLog)>,
+        reload_tx: Sender<ReloadRequest>,
+        health: Health,
+        db: Arc<DB>,
+    ) -> Self {
         Self {
-            valid_senders: Arc::new(addresses),
+            valid_senders: addresses,
             verifier_tx,
+            reload_tx,
+            health,
+            db,
         }
     }
 
-    pub async fn run_server(self, port: u16) -> Result<()> {
+    /// Serves until `shutdown` is set. New proofs are refused from then on, and the verifier
+    /// channel closes once the server is gone, so the rest of the pipeline can drain.
+    pub async fn run_server(self, port: u16, mut shutdown: watch::Receiver<bool>) -> Result<()> {
         let addr = format!("127.0.0.1:{}", port);
         tracing::info!("JSON RPC server running at {}", addr);
-        let server = Server::builder().build(addr).await?;
+        let listener = TcpListener::bind(&addr).await?;
         let mut module = RpcModule::new(());
 
         let server_handle = self.clone();
 
         module
-            .register_async_method("twarb_sendProof", move |params, _ctx, _| {
+            .register_async_method("twarb_sendProof", move |params, _ctx, extensions| {
                 tracing::info!("Received proof");
                 let server_handle = server_handle.clone();
+                let remote_addr = extensions.get::<SocketAddr>().map(|addr| addr.to_string());
                 async move {
+                    let log = SubmissionLog::received(remote_addr, proof_hash(&params));
                     let proof: ProofTypes = match params.one() {
                         Ok(p) => p,
                         Err(e) => {
-                            return ServerReturnType::Failure(format!(
-                                "Failed deserializing proof: {e:?}"
-                            ));
+                            let error = format!("Failed deserializing proof: {e:?}");
+                            let log = match claimed_identifier(&params) {
+                                Some(identifier) => log.with_identifier(&identifier),
+                                None => log,
+                            };
+                            server_handle
+                                .db
+                                .log_submission(log.rejected(SubmissionOutcome::Malformed, &error))
+                                .await;
+                            return ServerReturnType::Failure(error);
                         }
                     };
                     match proof {
-                        ProofTypes::RISC0Proof { .. } => {
+                        ProofTypes::RISC0Proof { identifier, .. } => {
                             tracing::info!("RISC0 Proof not supported at the moment");
+                            let log = log
+                                .with_identifier(&identifier)
+                                .with_proof_type(&SupportedProvers::RISC0.to_string())
+                                .rejected(SubmissionOutcome::Unsupported, "Not supported");
+                            server_handle.db.log_submission(log).await;
                             ServerReturnType::Failure("Not supported".to_string())
                         }
                         ProofTypes::SP1Proof { proof, identifier } => {
-                            match server_handle.handle_sp1_proof(proof, identifier).await {
+                            let log = log.with_identifier(&identifier);
+                            match server_handle.handle_sp1_proof(proof, identifier, log).await {
                                 Ok(_) => ServerReturnType::Success,
                                 Err(e) => {
                                     let error_msg = e.to_string();
@@ -83,7 +137,11 @@ impl JsonRpcServer {
                             }
                         }
                         ProofTypes::Dummy { proof, identifier } => {
-                            match server_handle.handle_dummy_proof(proof, identifier).await {
+                            let log = log.with_identifier(&identifier);
+                            match server_handle
+                                .handle_dummy_proof(proof, identifier, log)
+                                .await
+                            {
                                 Ok(_) => ServerReturnType::Success,
                                 Err(e) => {
                                     let error_msg = e.to_string();
@@ -96,14 +154,100 @@ impl JsonRpcServer {
             })
             .unwrap();
 
+        // latest submissions first, by prover and height when given
+        let db = Arc::clone(&self.db);
+        module.register_async_method("twarb_getSubmissions", move |params, _, _| {
+            let db = Arc::clone(&db);
+            async move {
+                let query: SubmissionQuery = params.one()?;
+                db.store
+                    .submissions(query)
+                    .await
+                    .map_err(|e| ErrorObjectOwned::owned(0, e.to_string(), None::<()>))
+            }
+        })?;
+
         module.register_method("twarb_healthCheck", |params, _, _| {
             let msg: String = params.one().unwrap();
             format!("Status: 1 Msg: {}", msg)
         })?;
 
-        let handle = server.start(module);
-        handle.stopped().await;
+        // status of every supervised component and the alerts that need an operator
+        let health = self.health.clone();
+        module.register_method("twarb_health", move |_, _, _| {
+            let components: BTreeMap<String, String> = health.snapshot();
+            serde_json::json!({
+                "healthy": health.healthy(),
+                "components": components,
+                "alerts": health.alerts(),
+            })
+        })?;
+
+        // admin method, the server only listens on localhost
+        let reload_tx = self.reload_tx.clone();
+        module.register_async_method("twarb_reloadConfig", move |_, _, _| {
+            tracing::info!("Config reload requested over RPC");
+            let reload_tx = reload_tx.clone();
+            async move {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                if reload_tx.send(reply_tx).await.is_err() {
+                    return Err(ErrorObjectOwned::owned(
+                        0,
+                        "Config reload is not available",
+                        None::<()>,
+                    ));
+                }
+                match reply_rx.await {
+                    Ok(Ok(changes)) => Ok(changes),
+                    Ok(Err(e)) => Err(ErrorObjectOwned::owned(0, e, None::<()>)),
+                    Err(_) => Err(ErrorObjectOwned::owned(
+                        0,
+                        "Config reload did not answer",
+                        None::<()>,
+                    )),
+                }
+            }
+        })?;
 
+        // connections are accepted here rather than by `Server::start`, so that the remote address
+        // reaches the methods through the request extensions
+        let methods: Methods = module.into();
+        let service_builder = Server::builder().to_service_builder();
+        let (stop_handle, server_handle) = stop_channel();
+        loop {
+            let (socket, remote_addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept connection. error:{}", e);
+                        continue;
+                    }
+                },
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+
+            let methods = methods.clone();
+            let service_builder = service_builder.clone();
+            let connection_stop = stop_handle.clone();
+            let service =
+                tower::service_fn(move |mut request: hyper::Request<hyper::body::Incoming>| {
+                    request.extensions_mut().insert(remote_addr);
+                    let mut service = service_builder
+                        .clone()
+                        .build(methods.clone(), connection_stop.clone());
+                    async move { service.call(request).await }
+                });
+            tokio::spawn(serve_with_graceful_shutdown(
+                socket,
+                service,
+                stop_handle.clone().shutdown(),
+            ));
+        }
+
+        tracing::info!("JSON RPC server no longer accepting proofs");
+        drop(stop_handle);
+        let _ = server_handle.stop();
+        server_handle.stopped().await;
         Ok(())
     }
 
@@ -111,23 +255,74 @@ impl JsonRpcServer {
         &self,
         proof: SP1ProofWithPublicValues,
         identifier: String,
+        log: SubmissionLog,
     ) -> Result<()> {
+        if !self.is_valid_sender(&identifier) {
+            return self
+                .reject_sender(identifier, SupportedProvers::SP1, log)
+                .await;
+        }
+
+        self.queue(ProofType::SP1Proof(proof, identifier), log)
+            .await
+    }
 
-        if !self.valid_senders.contains_key(&identifier) {
-            tracing::error!("Invalid sender. Identifier:{}", identifier);
-            return Err(AggregatorError::InvalidSender(identifier).into());
+    async fn handle_dummy_proof(
+        &self,
+        proof: Vec<u8>,
+        identifier: String,
+        log: SubmissionLog,
+    ) -> Result<()> {
+        if !self.is_valid_sender(&identifier) {
+            return self
+                .reject_sender(identifier, SupportedProvers::Dummy, log)
+                .await;
         }
 
-        self.verifier_tx
-            .send(ProofType::SP1Proof(proof, identifier))
-            .await?;
-        Ok(())
+        self.queue(ProofType::Dummy(proof, identifier), log).await
+    }
+
+    fn is_valid_sender(&self, identifier: &str) -> bool {
+        self.valid_senders.read().unwrap().contains_key(identifier)
+    }
+
+    /// Logs a proof from a prover that is not configured and refuses it
+    async fn reject_sender(
+        &self,
+        identifier: String,
+        proof_type: SupportedProvers,
+        log: SubmissionLog,
+    ) -> Result<()> {
+        tracing::error!("Invalid sender. Identifier:{}", identifier);
+        let error = AggregatorError::InvalidSender(identifier);
+        let log = log
+            .with_proof_type(&proof_type.to_string())
+            .rejected(SubmissionOutcome::InvalidSender, &error);
+        self.db.log_submission(log).await;
+        Err(error.into())
     }
 
-    async fn handle_dummy_proof(&self, proof: Vec<u8>, identifier: String) -> Result<()> {
-        self.verifier_tx
-            .send(ProofType::Dummy(proof, identifier))
-            .await?;
+    /// Hands the proof to the verifier, which logs the outcome
+    async fn queue(&self, proof: ProofType, log: SubmissionLog) -> Result<()> {
+        if let Err(e) = self.verifier_tx.send((proof, log)).await {
+            let (_, log) = e.0;
+            let error = AggregatorError::Custom("Verifier not running".to_string());
+            self.db
+                .log_submission(log.rejected(SubmissionOutcome::Error, &error))
+                .await;
+            return Err(error.into());
+        }
         Ok(())
     }
 }
+
+/// keccak256 of the request params as received, to tell submissions apart without storing them
+fn proof_hash(params: &Params) -> String {
+    keccak256(params.as_str().unwrap_or_default().as_bytes()).to_string()
+}
+
+/// Identifier of a request that could not be parsed as a proof, if it names one
+fn claimed_identifier(params: &Params) -> Option<String> {
+    let value: serde_json::Value = params.one().ok()?;
+    value.get("identifier")?.as_str().map(str::to_string)
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+
+use crate::{
+    chains::chains::{ChainProviders, L1State, LandedTx},
+    types::PostParams,
+};
+
+/// A batch whose settlement transactions landed on a chain but are not final yet
+#[derive(Clone)]
+pub struct PendingBatch {
+    pub params: PostParams,
+    pub txs: Vec<LandedTx>,
+}
+
+#[derive(Default)]
+pub struct FinalityReport {
+    /// Heights whose transactions are all final
+    pub finalized: Vec<u64>,
+    /// Batches whose transactions left the canonical chain and have to be posted again
+    pub reorged: Vec<PostParams>,
+}
+
+/// Keeps watching landed settlement transactions until they are final on their chain
+#[derive(Default)]
+pub struct FinalityWatcher {
+    pending: HashMap<String, BTreeMap<u64, PendingBatch>>,
+}
+
+impl FinalityWatcher {
+    pub fn watch(&mut self, chain: &str, params: PostParams, txs: Vec<LandedTx>) {
+        self.pending
+            .entry(chain.to_string())
+            .or_default()
+            .insert(params.height(), PendingBatch { params, txs });
+    }
+
+    /// Stops watching a chain that was removed from the config
+    pub fn forget(&mut self, chain: &str) {
+        self.pending.remove(chain);
+    }
+
+    pub fn is_watching(&self, chain: &str) -> bool {
+        self.pending.get(chain).is_some_and(|p| !p.is_empty())
+    }
+
+    /// Hands the batches of a chain to a check that runs apart from the watcher
+    pub fn take(&mut self, chain: &str) -> BTreeMap<u64, PendingBatch> {
+        self.pending.remove(chain).unwrap_or_default()
+    }
+
+    /// Puts back what is left of the batches after a check from `take`. Batches watched in the
+    /// meantime are kept, unless they come after a reorged one, then they are reorged as well.
+    pub fn restore(
+        &mut self,
+        chain: &str,
+        checked: BTreeMap<u64, PendingBatch>,
+        report: &mut FinalityReport,
+    ) {
+        let pending = self.pending.entry(chain.to_string()).or_default();
+        if let Some(from) = report.reorged.first().map(PostParams::height) {
+            report.reorged.extend(
+                pending
+                    .split_off(&from)
+                    .into_values()
+                    .map(|batch| batch.params),
+            );
+        }
+        for (height, batch) in checked {
+            pending.entry(height).or_insert(batch);
+        }
+    }
+
+    /// Checks the batches of a chain in place, see `check_pending`
+    pub async fn check(
+        &mut self,
+        chain: &str,
+        provider: &ChainProviders,
+    ) -> Result<FinalityReport> {
+        match self.pending.get_mut(chain) {
+            Some(pending) => check_pending(chain, provider, pending).await,
+            None => Ok(FinalityReport::default()),
+        }
+    }
+}
+
+/// Checks the landed transactions of a chain against its final block. If a transaction is no longer
+/// on the canonical chain, that height and every height after it are returned to be posted again,
+/// since later batches were built on top of it.
+pub async fn check_pending(
+    chain: &str,
+    provider: &ChainProviders,
+    pending: &mut BTreeMap<u64, PendingBatch>,
+) -> Result<FinalityReport> {
+    let mut report = FinalityReport::default();
+    if pending.is_empty() {
+        return Ok(report);
+    }
+
+    let final_height = provider.finalized_height().await?;
+    let mut reorged_from = None;
+
+    'heights: for (height, batch) in pending.iter_mut() {
+        let mut is_final = true;
+        for tx in batch.txs.iter_mut() {
+            match provider.tx_inclusion(&tx.tx_hash).await? {
+                Some(landed) => {
+                    if landed.block_hash != tx.block_hash {
+                        tracing::warn!(
+                            "Transaction moved to another block. chain:{} txn_hash:{} block:{}",
+                            chain,
+                            tx.tx_hash,
+                            landed.block_number
+                        );
+                        tx.block_number = landed.block_number;
+                        tx.block_hash = landed.block_hash;
+                    }
+                    is_final &= tx.block_number <= final_height;
+                }
+                None => {
+                    tracing::warn!(
+                        "Transaction reorged out. chain:{} height:{} txn_hash:{}",
+                        chain,
+                        height,
+                        tx.tx_hash
+                    );
+                    reorged_from = Some(*height);
+                    break 'heights;
+                }
+            }
+        }
+        if is_final {
+            report.finalized.push(*height);
+        }
+    }
+
+    for height in &report.finalized {
+        pending.remove(height);
+    }
+    if let Some(height) = reorged_from {
+        report.reorged = pending
+            .split_off(&height)
+            .into_values()
+            .map(|batch| batch.params)
+            .collect();
+    }
+    Ok(report)
+}
@@ -1 +1,3 @@
+pub mod finality;
 pub mod poster;
+pub mod reorder;
@@ -1,122 +1,751 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Result;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::{
+    sync::{
+        mpsc::{Receiver, Sender},
+        watch,
+    },
+    task::{JoinError, JoinSet},
+    time::interval,
+};
 use tracing::info;
 
 use crate::{
+    aggregator::supervisor::Health,
     chains::{
-        chains::{ChainProviders, FetchL2TransactionData, L1Transactions},
-        evm::provider::EVMProvider,
+        chains::{
+            ChainProviders, FetchL2TransactionData, L1Set, L1State, L1Transactions, LandedTx,
+        },
+        evm::sender::TwineChain::CommitBatchInfo,
     },
+    error::{post_action, AggregatorError, PostAction},
     types::PostParams,
 };
 
+use super::{
+    finality::{check_pending, FinalityReport, FinalityWatcher, PendingBatch},
+    reorder::ReorderBuffer,
+};
+
+/// Interval in seconds at which blocked heights are retried and reported
+static RETRY_INTERVAL: u64 = 30;
+/// Interval in seconds at which landed settlements are checked for finality
+static FINALITY_INTERVAL: u64 = 12;
+
+/// Commit batch info by height. It is the same for every chain, so it is fetched once.
+type CommitInfos = Arc<Mutex<HashMap<u64, CommitBatchInfo>>>;
+
+/// What a posting task reports: its chain, the number of batches it was given and every attempt
+type ChainAttempts = (
+    String,
+    usize,
+    Result<Vec<(PostParams, Result<SettlementTxs>)>, JoinError>,
+);
+
+/// What a finality task reports: its chain, the batches it checked and the outcome
+type FinalityCheck = (String, BTreeMap<u64, PendingBatch>, Result<FinalityReport>);
+
 pub struct PostStatus {
     pub chain: String,
     pub block: u64,
     pub posted: bool,
 }
 
+/// Transactions that landed for a batch on one chain. None for steps the contract had already done.
+#[derive(Debug, Default)]
+pub struct SettlementTxs {
+    pub commit: Option<LandedTx>,
+    pub finalize: Option<LandedTx>,
+}
+
+impl SettlementTxs {
+    pub fn landed(&self) -> Vec<LandedTx> {
+        self.commit
+            .iter()
+            .chain(self.finalize.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// One settlement attempt of a block on a chain
+pub struct SettlementAttempt {
+    pub chain: String,
+    pub block: u64,
+    pub txs: SettlementTxs,
+    /// Set when the attempt failed
+    pub error: Option<String>,
+}
+
+/// What the poster reports to the DB
+pub enum PostUpdate {
+    Attempt(SettlementAttempt),
+    /// The block became final on the chain, or was reorged out
+    Status(PostStatus),
+}
+
 pub struct Poster {
     pub providers: HashMap<String, ChainProviders>,
-    pub post_status_tx: Sender<PostStatus>,
-    pub l2_provider: EVMProvider,
+    pub post_status_tx: Sender<PostUpdate>,
+    pub l2_provider: ChainProviders,
+    pub buffer: ReorderBuffer,
+    pub finality: FinalityWatcher,
+    /// L1s of the latest config, replaced on reload
+    pub l1_updates: watch::Receiver<L1Set>,
+    commit_infos: CommitInfos,
+    /// Chains with a posting task in flight, each chain posts on its own
+    posting: HashSet<String>,
+    /// Chains whose last task failed, they wait for the next retry tick
+    backoff: HashSet<String>,
+    /// Chains with a finality check running
+    checking: HashSet<String>,
+    /// Chains stopped at a batch that retrying will not settle, with its height. They stay
+    /// stopped until an operator fixes the batch and restarts the aggregator.
+    pub parked: HashMap<String, u64>,
+    health: Health,
 }
 
 impl Poster {
     pub fn new(
-        l1s: HashMap<String, ChainProviders>,
-        post_status_tx: Sender<PostStatus>,
-        l2_provider: EVMProvider,
+        l1_updates: watch::Receiver<L1Set>,
+        post_status_tx: Sender<PostUpdate>,
+        l2_provider: ChainProviders,
+        start_height: Option<u64>,
     ) -> Self {
+        let l1s = l1_updates.borrow().providers.clone();
+        let buffer = ReorderBuffer::new(l1s.keys().cloned(), start_height);
         Self {
             providers: l1s,
             post_status_tx,
             l2_provider,
+            buffer,
+            finality: FinalityWatcher::default(),
+            l1_updates,
+            commit_infos: CommitInfos::default(),
+            posting: HashSet::new(),
+            backoff: HashSet::new(),
+            checking: HashSet::new(),
+            parked: HashMap::new(),
+            health: Health::default(),
         }
     }
 
+    /// Alerts for parked chains are raised here, so they show up in `twarb_health`
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
     /// The data field incoming in the channel expects all the required parameters to post to the contract
     /// For verifying proof, it'll just be the public inputs and proof
-    /// TODO: Communicate with the contract team for the structure
-    pub async fn run(&mut self, mut post_rx: Receiver<PostParams>) -> Result<()> {
+    /// Batches are held in the reorder buffer and posted to each L1 strictly in height order.
+    /// Every chain posts and checks finality from its own tasks, so a chain stuck on a transaction
+    /// or a slow endpoint holds back neither the other chains nor the intake of new batches.
+    pub async fn run(&mut self, post_rx: &mut Receiver<PostParams>) -> Result<()> {
         tracing::info!("Prover service running");
-        while let Some(data) = post_rx.recv().await {
-            tracing::info!("Ready for commit batch and finalize batch");
-            // handle batch number here
-            let l2_height = data.height();
-            let commit_batch_info = self.l2_provider.fetch_commit_batch(l2_height).await?;
-            let providers = self.providers.clone();
-            let mut tasks = vec![];
-
-            for (chain, provider) in providers {
-                let chain = chain.to_string();
-                let batch = commit_batch_info.clone();
-                let batch_number = batch.batchNumber;
-                let data_clone = data.clone();
-                let post_status_tx = self.post_status_tx.clone();
-
-                let task = tokio::spawn(async move {
-                    match provider.commit_batch(batch, l2_height).await {
-                        Ok(_) => {
-                            tracing::info!(
-                                "Batch committed! batch: {} chain: {}",
-                                batch_number,
-                                chain
-                            );
-                        }
-                        Err(_) => {
-                            // notify
-                            tracing::error!(
-                                "Failed posting batch: {} chain: {}",
-                                batch_number,
-                                chain
-                            );
-                        }
+        self.sync_with_l1s().await;
+        let mut retry = interval(Duration::from_secs(RETRY_INTERVAL));
+        let mut finality = interval(Duration::from_secs(FINALITY_INTERVAL));
+        let mut l1_updates = self.l1_updates.clone();
+        let mut tasks: JoinSet<ChainAttempts> = JoinSet::new();
+        let mut checks: JoinSet<FinalityCheck> = JoinSet::new();
+        loop {
+            tokio::select! {
+                data = post_rx.recv() => {
+                    let Some(data) = data else {
+                        tracing::info!("Verifier stopped, posting remaining batches");
+                        break;
+                    };
+                    let height = data.height();
+                    if self.buffer.insert(data) {
+                        tracing::info!("Batch buffered for posting. height:{}", height);
+                    } else {
+                        tracing::info!("Batch already settled on all chains. height:{}", height);
                     }
+                }
+                Some(Ok(attempts)) = tasks.join_next() => {
+                    self.record_attempts(attempts).await;
+                }
+                Some(Ok(checked)) = checks.join_next() => {
+                    self.record_finality(checked).await;
+                }
+                _ = retry.tick() => {
+                    self.backoff.clear();
+                    self.report_gaps();
+                }
+                _ = finality.tick() => {
+                    self.start_finality_checks(&mut checks);
+                }
+                Ok(()) = l1_updates.changed() => {
+                    let l1s = l1_updates.borrow_and_update().clone();
+                    self.apply_l1_update(l1s).await;
+                }
+            }
+            self.post_ready_batches(&mut tasks);
+        }
 
-                    match provider.submit_proof(data_clone.clone()).await {
-                        Ok(_) => {
-                            tracing::info!("Proof submitted. chain:{}", chain);
-                            let post_status = match data_clone {
-                                PostParams::RiscZero(_risc0_params, _) => todo!(),
-                                PostParams::Sp1(_sp1params, block) => PostStatus {
-                                    chain: chain.clone(),
-                                    block,
-                                    posted: true,
-                                },
-                                PostParams::Dummy(_, block) => PostStatus {
-                                    chain: chain.clone(),
-                                    block,
-                                    posted: true,
-                                },
-                            };
-                            info!("Post status received. Sending status to post_status channel");
-                            if let Err(e) = post_status_tx.send(post_status).await {
-                                tracing::error!(
-                                    "Failed to send post status. chain: {} error: {}",
-                                    chain,
-                                    e.to_string()
-                                );
-                            }
-                        }
-                        Err(_) => {
-                            // notify
-                            tracing::error!("Fail to submit proof. chain:{}", chain);
-                        }
+        // last pass before shutting down, what is left is posted from the DB on the next start
+        self.post_ready_batches(&mut tasks);
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok(attempts) = joined {
+                self.record_attempts(attempts).await;
+            }
+            self.post_ready_batches(&mut tasks);
+        }
+        while let Some(joined) = checks.join_next().await {
+            if let Ok(checked) = joined {
+                self.record_finality(checked).await;
+            }
+        }
+        self.check_finality().await;
+        if !self.buffer.is_empty() {
+            tracing::warn!(
+                "Poster stopped with unposted batches. buffered:{}",
+                self.buffer.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Buffers batches that were verified but not posted everywhere before the last shutdown
+    pub fn restore(&mut self, batches: Vec<PostParams>) {
+        let mut restored = 0;
+        for params in batches {
+            if self.buffer.insert(params) {
+                restored += 1;
+            }
+        }
+        tracing::info!("Restored unposted batches from DB. count:{}", restored);
+    }
+
+    /// Starts each chain cursor right after the last batch finalized on its contract
+    async fn sync_with_l1s(&mut self) {
+        for (chain, provider) in self.providers.clone() {
+            self.sync_chain(&chain, &provider).await;
+        }
+    }
+
+    async fn sync_chain(&mut self, chain: &str, provider: &ChainProviders) {
+        match provider.last_finalized_batch().await {
+            Ok(last_finalized) => {
+                tracing::info!(
+                    "Last finalized batch on contract. chain:{} batch:{}",
+                    chain,
+                    last_finalized
+                );
+                self.buffer.set_next_height(chain, last_finalized + 1);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to read last finalized batch, using configured start height. chain:{} error:{}",
+                    chain,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Switches to the L1s of a reloaded config. Removed chains are dropped with their pending batches,
+    /// added chains start after the last batch finalized on their contract.
+    /// Changed chains keep their place and use the new provider from the next post on.
+    async fn apply_l1_update(&mut self, l1s: L1Set) {
+        for chain in self.providers.keys() {
+            if !l1s.providers.contains_key(chain) {
+                tracing::info!("Stopped posting to removed chain. chain:{}", chain);
+                self.buffer.remove_chain(chain);
+                self.finality.forget(chain);
+                if self.parked.remove(chain).is_some() {
+                    self.health.clear_alert(&parked_alert(chain));
+                }
+            }
+        }
+
+        let added: Vec<(String, ChainProviders)> = l1s
+            .providers
+            .iter()
+            .filter(|(chain, _)| !self.providers.contains_key(*chain))
+            .map(|(chain, provider)| (chain.clone(), provider.clone()))
+            .collect();
+        self.providers = l1s.providers;
+
+        for (chain, provider) in added {
+            tracing::info!("Started posting to added chain. chain:{}", chain);
+            self.buffer.add_chain(&chain);
+            self.sync_chain(&chain, &provider).await;
+        }
+    }
+
+    /// Starts a posting task for every chain that has batches next in line and is not posting yet.
+    /// Heights within a chain are posted one after another.
+    fn post_ready_batches(&mut self, tasks: &mut JoinSet<ChainAttempts>) {
+        for (chain, provider) in self.providers.clone() {
+            if self.posting.contains(&chain)
+                || self.backoff.contains(&chain)
+                || self.parked.contains_key(&chain)
+            {
+                continue;
+            }
+            let ready = self.buffer.ready_for(&chain);
+            if ready.is_empty() {
+                continue;
+            }
+
+            self.posting.insert(chain.clone());
+            let l2_provider = self.l2_provider.clone();
+            let commit_infos = Arc::clone(&self.commit_infos);
+            tasks.spawn(async move {
+                let given = ready.len();
+                // run apart so that a panic is reported for the chain instead of losing it
+                let posting = tokio::spawn({
+                    let chain = chain.clone();
+                    async move {
+                        post_in_order(&chain, provider, &l2_provider, &commit_infos, ready).await
                     }
                 });
-                tasks.push(task);
+                (chain, given, posting.await)
+            });
+        }
+    }
+
+    /// Applies the outcome of a posting task. A chain that did not settle everything it was
+    /// given waits for the next retry tick.
+    async fn record_attempts(&mut self, (chain, given, posting): ChainAttempts) {
+        self.posting.remove(&chain);
+        let attempts = match posting {
+            Ok(attempts) => attempts,
+            Err(e) => {
+                tracing::error!("Posting task failed. chain:{} error:{}", chain, e);
+                self.backoff.insert(chain);
+                return;
             }
+        };
+        let active = self.providers.contains_key(&chain);
+        let mut settled = 0;
 
-            // Wait for all spawned tasks to complete
-            for task in tasks {
-                if let Err(err) = task.await {
-                    tracing::error!("Task failed with error: {:?}", err);
+        for (params, result) in attempts {
+            let height = params.height();
+            let (txs, error) = match result {
+                Ok(txs) => {
+                    settled += 1;
+                    self.buffer.mark_settled(&chain, height);
+                    if active {
+                        self.finality.watch(&chain, params, txs.landed());
+                    }
+                    (txs, None)
                 }
+                Err(e) => {
+                    if post_action(&e) == PostAction::Alert {
+                        self.park(&chain, height, &e);
+                    }
+                    (SettlementTxs::default(), Some(e.to_string()))
+                }
+            };
+            self.send_update(PostUpdate::Attempt(SettlementAttempt {
+                chain: chain.clone(),
+                block: height,
+                txs,
+                error,
+            }))
+            .await;
+        }
+        if settled < given {
+            self.backoff.insert(chain);
+        }
+
+        let buffer = &self.buffer;
+        self.commit_infos
+            .lock()
+            .unwrap()
+            .retain(|height, _| buffer.contains(*height));
+    }
+
+    /// Stops posting to the chain at a batch that needs an operator. Later batches stay buffered.
+    fn park(&mut self, chain: &str, height: u64, error: &anyhow::Error) {
+        tracing::error!(
+            "ALERT: posting stopped until the batch is fixed and the aggregator restarted. chain:{} height:{} error:{}",
+            chain,
+            height,
+            error
+        );
+        self.health.raise_alert(
+            parked_alert(chain),
+            format!("batch {} cannot be settled: {}", height, error),
+        );
+        self.parked.insert(chain.to_string(), height);
+    }
+
+    /// Starts a finality check for every chain with landed batches that is not being checked yet
+    fn start_finality_checks(&mut self, checks: &mut JoinSet<FinalityCheck>) {
+        for (chain, provider) in self.providers.clone() {
+            if self.checking.contains(&chain) || !self.finality.is_watching(&chain) {
+                continue;
             }
+
+            self.checking.insert(chain.clone());
+            let pending = self.finality.take(&chain);
+            checks.spawn(async move {
+                // the batches are kept here in case the check panics
+                let kept = pending.clone();
+                let checking = tokio::spawn({
+                    let chain = chain.clone();
+                    async move {
+                        let mut pending = pending;
+                        let report = check_pending(&chain, &provider, &mut pending).await;
+                        (pending, report)
+                    }
+                });
+                match checking.await {
+                    Ok((pending, report)) => (chain, pending, report),
+                    Err(e) => (chain, kept, Err(e.into())),
+                }
+            });
         }
-        Ok(())
+    }
+
+    /// Applies the outcome of a finality task. The batches go back to the watcher for the next check.
+    async fn record_finality(&mut self, (chain, pending, report): FinalityCheck) {
+        self.checking.remove(&chain);
+        if !self.providers.contains_key(&chain) {
+            return;
+        }
+        match report {
+            Ok(mut report) => {
+                self.finality.restore(&chain, pending, &mut report);
+                self.apply_finality(&chain, report).await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to check finality. chain:{} error:{}", chain, e);
+                self.finality
+                    .restore(&chain, pending, &mut FinalityReport::default());
+            }
+        }
+    }
+
+    /// Checks every chain in turn, for the last pass on shutdown
+    async fn check_finality(&mut self) {
+        for (chain, provider) in self.providers.clone() {
+            if !self.finality.is_watching(&chain) {
+                continue;
+            }
+            match self.finality.check(&chain, &provider).await {
+                Ok(report) => self.apply_finality(&chain, report).await,
+                Err(e) => {
+                    tracing::warn!("Failed to check finality. chain:{} error:{}", chain, e);
+                }
+            }
+        }
+    }
+
+    /// Marks landed batches as posted once they are final on their chain.
+    /// Batches that were reorged out are marked unposted and go back into the buffer.
+    async fn apply_finality(&mut self, chain: &str, report: FinalityReport) {
+        for height in report.finalized {
+            tracing::info!("Settlement final. chain:{} height:{}", chain, height);
+            self.send_post_status(chain, height, true).await;
+        }
+        for params in &report.reorged {
+            self.send_post_status(chain, params.height(), false).await;
+        }
+        if !report.reorged.is_empty() {
+            tracing::warn!(
+                "Re-submitting reorged batches. chain:{} heights:{:?}",
+                chain,
+                report
+                    .reorged
+                    .iter()
+                    .map(|p| p.height())
+                    .collect::<Vec<_>>()
+            );
+            self.buffer.rewind(chain, report.reorged);
+        }
+    }
+
+    async fn send_post_status(&self, chain: &str, block: u64, posted: bool) {
+        let post_status = PostStatus {
+            chain: chain.to_string(),
+            block,
+            posted,
+        };
+        info!("Post status received. Sending status to post_status channel");
+        self.send_update(PostUpdate::Status(post_status)).await;
+    }
+
+    async fn send_update(&self, update: PostUpdate) {
+        if let Err(e) = self.post_status_tx.send(update).await {
+            tracing::error!("Failed to send post update. error: {}", e.to_string());
+        }
+    }
+
+    /// Logs the heights that keep buffered batches from being posted
+    fn report_gaps(&self) {
+        for (chain, height) in &self.parked {
+            tracing::error!(
+                "ALERT: posting parked at a batch that needs attention. chain:{} height:{}",
+                chain,
+                height
+            );
+        }
+        for (chain, gaps) in self.buffer.gaps() {
+            tracing::warn!(
+                "Posting blocked by missing heights. chain:{} next_height:{:?} missing:{:?} buffered:{}",
+                chain,
+                self.buffer.next_height(&chain),
+                gaps,
+                self.buffer.len()
+            );
+        }
+    }
+}
+
+/// Subject of the health alert raised for a parked chain
+fn parked_alert(chain: &str) -> String {
+    format!("poster:{}", chain)
+}
+
+/// Commits and finalizes the batches on one chain in order, stopping at the first failure
+/// so that a later height is never posted before an earlier one.
+/// Returns every attempted batch with its result, a failed one is always last. A batch whose
+/// commit info could not be fetched is not attempted.
+async fn post_in_order(
+    chain: &str,
+    provider: ChainProviders,
+    l2_provider: &ChainProviders,
+    commit_infos: &CommitInfos,
+    batches: Vec<PostParams>,
+) -> Vec<(PostParams, Result<SettlementTxs>)> {
+    let mut attempts = vec![];
+    for data in batches {
+        let height = data.height();
+        let batch = match commit_info(l2_provider, commit_infos, height).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to fetch commit batch. chain:{} height:{} error:{}",
+                    chain,
+                    height,
+                    e
+                );
+                break;
+            }
+        };
+        let batch_number = batch.batchNumber;
+
+        match settle_batch(chain, &provider, data.clone(), batch).await {
+            Ok(txs) => {
+                attempts.push((data, Ok(txs)));
+            }
+            Err(e) => {
+                match post_action(&e) {
+                    PostAction::Alert => {
+                        // notify
+                        tracing::error!(
+                            "ALERT: settlement needs attention. batch: {} chain: {} error: {}",
+                            batch_number,
+                            chain,
+                            e
+                        );
+                    }
+                    _ => {
+                        tracing::error!(
+                            "Failed settling batch, will retry: {} chain: {} error: {}",
+                            batch_number,
+                            chain,
+                            e
+                        );
+                    }
+                }
+                attempts.push((data, Err(e)));
+                break;
+            }
+        }
+    }
+    attempts
+}
+
+/// Commit batch info of the height, fetched from the L2 unless another chain already did
+async fn commit_info(
+    l2_provider: &ChainProviders,
+    commit_infos: &CommitInfos,
+    height: u64,
+) -> Result<CommitBatchInfo> {
+    if let Some(info) = commit_infos.lock().unwrap().get(&height) {
+        return Ok(info.clone());
+    }
+    let info = l2_provider.fetch_commit_batch(height).await?;
+    commit_infos.lock().unwrap().insert(height, info.clone());
+    Ok(info)
+}
+
+/// Commits and finalizes one batch on a chain. Steps that the contract has already done are skipped,
+/// so re-running the same batch after a restart or a manual relay does not send duplicate transactions.
+/// Returns the transactions that were sent and landed.
+pub async fn settle_batch(
+    chain: &str,
+    provider: &ChainProviders,
+    data: PostParams,
+    batch: CommitBatchInfo,
+) -> Result<SettlementTxs> {
+    let l2_height = data.height();
+    let batch_number = batch.batchNumber;
+
+    let last_finalized = provider.last_finalized_batch().await?;
+    if last_finalized >= batch_number {
+        tracing::info!(
+            "Batch already finalized on contract, skipping. batch: {} chain: {}",
+            batch_number,
+            chain
+        );
+        return Ok(SettlementTxs::default());
+    }
+
+    let mut landed = SettlementTxs::default();
+    let last_committed = provider.last_committed_batch().await?;
+    if last_committed >= batch_number {
+        let stored_root = provider.state_root(batch_number).await?;
+        if stored_root != batch.stateRoot {
+            return Err(AggregatorError::PosterError(format!(
+                "Committed state root {} does not match L2 state root {} for batch {}",
+                stored_root, batch.stateRoot, batch_number
+            ))
+            .into());
+        }
+        tracing::info!(
+            "Batch already committed on contract, skipping commit. batch: {} chain: {}",
+            batch_number,
+            chain
+        );
+    } else {
+        match provider.commit_batch(batch, l2_height).await {
+            Ok(tx) => {
+                tracing::info!("Batch committed! batch: {} chain: {}", batch_number, chain);
+                landed.commit = tx;
+            }
+            Err(e) if post_action(&e) == PostAction::Skip => {
+                tracing::info!(
+                    "Skipping commit. batch: {} chain: {} reason: {}",
+                    batch_number,
+                    chain,
+                    e
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match provider.submit_proof(data).await {
+        Ok(tx) => {
+            tracing::info!("Proof submitted. chain:{}", chain);
+            landed.finalize = tx;
+        }
+        Err(e) if post_action(&e) == PostAction::Skip => {
+            tracing::info!(
+                "Skipping finalize. batch: {} chain: {} reason: {}",
+                batch_number,
+                chain,
+                e
+            );
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(landed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tokio::sync::{mpsc, watch};
+
+    use super::Poster;
+    use crate::{
+        aggregator::supervisor::Health,
+        chains::{
+            chains::{ChainProviders, L1Set, L1State},
+            dummy::DummyProvider,
+        },
+        database::db::DB,
+        types::{DummyParams, PostParams},
+    };
+
+    fn batch(height: u64) -> PostParams {
+        PostParams::Dummy(DummyParams { proof: vec![] }, height)
+    }
+
+    #[tokio::test]
+    async fn test_posts_in_order_and_records_status() {
+        let l1 = DummyProvider::default();
+        let (_l1s_tx, l1s_rx) = watch::channel(L1Set {
+            providers: HashMap::from([("dummy".to_string(), ChainProviders::DummyVM(l1.clone()))]),
+            balance_thresholds: HashMap::new(),
+        });
+        let (post_status_tx, mut post_status_rx) = mpsc::channel(10);
+        let mut poster = Poster::new(
+            l1s_rx,
+            post_status_tx,
+            ChainProviders::DummyVM(DummyProvider::default()),
+            Some(1),
+        );
+
+        let (post_tx, mut post_rx) = mpsc::channel(10);
+        post_tx.send(batch(2)).await.unwrap();
+        post_tx.send(batch(1)).await.unwrap();
+        drop(post_tx);
+        poster.run(&mut post_rx).await.unwrap();
+        drop(poster);
+
+        assert_eq!(l1.last_finalized_batch().await.unwrap(), 2);
+
+        let db = DB::new(1, "memory://".to_string(), None).await;
+        db.run(&mut post_status_rx).await.unwrap();
+        let statuses = db.store.post_statuses().await.unwrap();
+        assert!(statuses[&1]["dummy"]);
+        assert!(statuses[&2]["dummy"]);
+
+        let settlement = &db.store.settlements(2).await.unwrap()[0];
+        assert_eq!(settlement.attempts, 1);
+        assert_eq!(settlement.commit.as_ref().unwrap().tx_hash, "commit-2");
+        assert_eq!(settlement.finalize.as_ref().unwrap().tx_hash, "finalize-2");
+        assert!(settlement.finalized_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_parks_chain_on_rejected_proof() {
+        let good = DummyProvider::default();
+        let bad = DummyProvider::default();
+        bad.reject_proof(1);
+        let (_l1s_tx, l1s_rx) = watch::channel(L1Set {
+            providers: HashMap::from([
+                ("good".to_string(), ChainProviders::DummyVM(good.clone())),
+                ("bad".to_string(), ChainProviders::DummyVM(bad.clone())),
+            ]),
+            balance_thresholds: HashMap::new(),
+        });
+        let (post_status_tx, _post_status_rx) = mpsc::channel(20);
+        let health = Health::default();
+        let mut poster = Poster::new(
+            l1s_rx,
+            post_status_tx,
+            ChainProviders::DummyVM(DummyProvider::default()),
+            Some(1),
+        )
+        .with_health(health.clone());
+
+        let (post_tx, mut post_rx) = mpsc::channel(10);
+        post_tx.send(batch(1)).await.unwrap();
+        post_tx.send(batch(2)).await.unwrap();
+        drop(post_tx);
+        poster.run(&mut post_rx).await.unwrap();
+
+        assert_eq!(good.last_finalized_batch().await.unwrap(), 2);
+        assert_eq!(bad.last_finalized_batch().await.unwrap(), 0);
+        assert_eq!(bad.last_committed_batch().await.unwrap(), 1);
+        assert_eq!(poster.parked["bad"], 1);
+        assert!(health.alerts().contains_key("poster:bad"));
+        assert!(!health.healthy());
     }
 }
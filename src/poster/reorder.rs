@@ -0,0 +1,204 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::RangeInclusive,
+};
+
+use crate::types::PostParams;
+
+/// Holds threshold verified batches until every lower height has been settled on a chain.
+/// Each L1 keeps its own cursor, so a chain that is stuck on one height does not hold back the others.
+pub struct ReorderBuffer {
+    batches: BTreeMap<u64, PostParams>,
+    next_height: HashMap<String, Option<u64>>,
+}
+
+impl ReorderBuffer {
+    /// `start_height` is the first height expected on every chain. If it is not known,
+    /// the first height that arrives is used as the starting point.
+    pub fn new(chains: impl IntoIterator<Item = String>, start_height: Option<u64>) -> Self {
+        Self {
+            batches: BTreeMap::new(),
+            next_height: chains
+                .into_iter()
+                .map(|chain| (chain, start_height))
+                .collect(),
+        }
+    }
+
+    /// Buffers the batch. Returns false if the height is already settled on every chain.
+    pub fn insert(&mut self, params: PostParams) -> bool {
+        let height = params.height();
+        for next in self.next_height.values_mut() {
+            if next.is_none() {
+                tracing::warn!(
+                    "Start height unknown, posting from first received height:{}",
+                    height
+                );
+                *next = Some(height);
+            }
+        }
+
+        if self
+            .lowest_unsettled()
+            .is_some_and(|lowest| height < lowest)
+        {
+            return false;
+        }
+        self.batches.insert(height, params);
+        true
+    }
+
+    /// Next height to be settled on the chain
+    pub fn next_height(&self, chain: &str) -> Option<u64> {
+        self.next_height.get(chain).copied().flatten()
+    }
+
+    /// Moves the chain cursor, e.g. to the height after the last batch finalized on the contract
+    pub fn set_next_height(&mut self, chain: &str, height: u64) {
+        if let Some(next) = self.next_height.get_mut(chain) {
+            *next = Some(height);
+        }
+        if let Some(lowest) = self.lowest_unsettled() {
+            self.batches = self.batches.split_off(&lowest);
+        }
+    }
+
+    /// Starts tracking a chain added by a config reload. Its cursor is unknown until it is set.
+    pub fn add_chain(&mut self, chain: &str) {
+        self.next_height.entry(chain.to_string()).or_insert(None);
+    }
+
+    /// Stops tracking a chain and drops the batches that only it was waiting for
+    pub fn remove_chain(&mut self, chain: &str) {
+        self.next_height.remove(chain);
+        if let Some(lowest) = self.lowest_unsettled() {
+            self.batches = self.batches.split_off(&lowest);
+        }
+    }
+
+    /// Puts batches back for a chain whose transactions were reorged out and moves its cursor back
+    pub fn rewind(&mut self, chain: &str, batches: Vec<PostParams>) {
+        let Some(lowest) = batches.iter().map(|p| p.height()).min() else {
+            return;
+        };
+        for params in batches {
+            self.batches.insert(params.height(), params);
+        }
+        if let Some(next) = self.next_height.get_mut(chain) {
+            match next {
+                Some(n) if *n <= lowest => {}
+                _ => *next = Some(lowest),
+            }
+        }
+    }
+
+    /// Heights buffered for the chain that can be posted in order right now
+    pub fn ready_for(&self, chain: &str) -> Vec<PostParams> {
+        let mut ready = vec![];
+        let Some(mut next) = self.next_height(chain) else {
+            return ready;
+        };
+        while let Some(params) = self.batches.get(&next) {
+            ready.push(params.clone());
+            next += 1;
+        }
+        ready
+    }
+
+    /// Advances the chain cursor past `height` and drops batches that are settled on every chain.
+    pub fn mark_settled(&mut self, chain: &str, height: u64) {
+        if let Some(next) = self.next_height.get_mut(chain) {
+            match next {
+                Some(n) if height < *n => {}
+                _ => *next = Some(height + 1),
+            }
+        }
+        if let Some(lowest) = self.lowest_unsettled() {
+            self.batches = self.batches.split_off(&lowest);
+        }
+    }
+
+    /// For every chain that has buffered batches it cannot post yet, returns the missing
+    /// height ranges between the chain cursor and the highest buffered height.
+    pub fn gaps(&self) -> HashMap<String, Vec<RangeInclusive<u64>>> {
+        let mut gaps = HashMap::new();
+        for (chain, next) in &self.next_height {
+            let Some(next) = *next else {
+                continue;
+            };
+            let mut missing = vec![];
+            let mut expected = next;
+            for height in self.batches.range(next..).map(|(h, _)| *h) {
+                if height > expected {
+                    missing.push(expected..=height - 1);
+                }
+                expected = height + 1;
+            }
+            if !missing.is_empty() {
+                gaps.insert(chain.clone(), missing);
+            }
+        }
+        gaps
+    }
+
+    /// Whether a batch of the height is still waiting for some chain
+    pub fn contains(&self, height: u64) -> bool {
+        self.batches.contains_key(&height)
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    fn lowest_unsettled(&self) -> Option<u64> {
+        self.next_height.values().copied().flatten().min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReorderBuffer;
+    use crate::types::{DummyParams, PostParams};
+
+    fn batch(height: u64) -> PostParams {
+        PostParams::Dummy(DummyParams { proof: vec![] }, height)
+    }
+
+    fn heights(ready: Vec<PostParams>) -> Vec<u64> {
+        ready.iter().map(|p| p.height()).collect()
+    }
+
+    #[test]
+    fn test_holds_out_of_order_heights() {
+        let mut buffer = ReorderBuffer::new(vec!["ethereum".to_string()], Some(104));
+        buffer.insert(batch(105));
+        assert!(buffer.ready_for("ethereum").is_empty());
+        assert_eq!(buffer.gaps()["ethereum"], vec![104..=104]);
+
+        buffer.insert(batch(104));
+        assert_eq!(heights(buffer.ready_for("ethereum")), vec![104, 105]);
+        assert!(buffer.gaps().is_empty());
+    }
+
+    #[test]
+    fn test_chains_progress_independently() {
+        let chains = vec!["ethereum".to_string(), "bnb".to_string()];
+        let mut buffer = ReorderBuffer::new(chains, Some(1));
+        buffer.insert(batch(1));
+        buffer.insert(batch(2));
+
+        buffer.mark_settled("ethereum", 1);
+        buffer.mark_settled("ethereum", 2);
+        assert!(buffer.ready_for("ethereum").is_empty());
+        assert_eq!(heights(buffer.ready_for("bnb")), vec![1, 2]);
+        assert_eq!(buffer.len(), 2);
+
+        buffer.mark_settled("bnb", 1);
+        assert_eq!(buffer.len(), 1);
+        assert!(!buffer.insert(batch(1)));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod secret;
+
 use regex::Regex;
 use std::path::Path;
 
@@ -6,6 +8,12 @@ pub fn is_valid_url(url: &str) -> bool {
     url_regex.is_match(url)
 }
 
+/// Like `is_valid_url`, but also accepts websocket urls and ipc socket paths
+pub fn is_valid_rpc_url(url: &str) -> bool {
+    let url_regex = Regex::new(r"^(http|https|ws|wss)://[^\s/$.?#].[^\s]*$").unwrap();
+    url_regex.is_match(url) || url.ends_with(".ipc")
+}
+
 pub fn check_directory_exists(path: &str) -> bool {
     let dir_path = Path::new(path);
     dir_path.is_file()
@@ -4,7 +4,11 @@ use anyhow::Result;
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
-    database::db::DB,
+    database::{
+        db::DB,
+        schema::{SubmissionLog, SubmissionOutcome},
+        storage::ProofInsert,
+    },
     types::{PostParams, ProofType, SupportedProvers},
 };
 
@@ -16,30 +20,66 @@ pub trait ProofTraits {
 }
 
 pub struct Verifier {
-    pub verifier_rx: Receiver<ProofType>,
+    pub verifier_rx: Receiver<(ProofType, SubmissionLog)>,
     pub db: Arc<DB>,
 }
 
 impl Verifier {
-    pub fn new(validator_rx: Receiver<ProofType>, db: Arc<DB>) -> Self {
+    pub fn new(validator_rx: Receiver<(ProofType, SubmissionLog)>, db: Arc<DB>) -> Self {
         Self {
             verifier_rx: validator_rx,
             db,
         }
     }
 
-    pub async fn run(&mut self, sp1: SP1, poster_tx: Sender<PostParams>) -> Result<()> {
+    /// A proof that cannot be handled is logged and skipped, it does not stop the verifier.
+    /// SP1 proofs are refused when no SP1 verifier is given.
+    /// Every proof ends up in the submission log with its outcome.
+    pub async fn run(&mut self, sp1: Option<&SP1>, poster_tx: Sender<PostParams>) -> Result<()> {
         tracing::info!("Verifier service running");
-        while let Some(proof) = self.verifier_rx.recv().await {
+        while let Some((proof, log)) = self.verifier_rx.recv().await {
             let poster_tx = poster_tx.clone();
-            match proof {
+            let log = match proof {
                 ProofType::SP1Proof(sp1_proof_with_public_values, identifier) => {
+                    let log = log.with_proof_type(&SupportedProvers::SP1.to_string());
+                    let log =
+                        match claimed_height(sp1_proof_with_public_values.public_values.as_slice())
+                        {
+                            Some(height) => log.with_height(height),
+                            None => log,
+                        };
+                    let Some(sp1) = sp1 else {
+                        tracing::error!("SP1 verifier not loaded. client={}", identifier);
+                        self.db
+                            .log_submission(log.rejected(
+                                SubmissionOutcome::Unsupported,
+                                "SP1 verifier not loaded",
+                            ))
+                            .await;
+                        continue;
+                    };
                     match sp1.verify_sp1_proof(sp1_proof_with_public_values.clone()) {
                         Ok(height) => {
                             tracing::info!("Proof verified. proof_type=sp1 client={}", identifier);
-                            let raw_string = serde_json::to_string(&sp1_proof_with_public_values)?;
+                            let raw_string =
+                                match serde_json::to_string(&sp1_proof_with_public_values) {
+                                    Ok(raw_string) => raw_string,
+                                    Err(e) => {
+                                        tracing::error!(
+                                            "Failed to serialize proof. client={} error={}",
+                                            identifier,
+                                            e
+                                        );
+                                        self.db
+                                            .log_submission(
+                                                log.rejected(SubmissionOutcome::Error, e),
+                                            )
+                                            .await;
+                                        continue;
+                                    }
+                                };
 
-                            let _ = self
+                            let result = self
                                 .db
                                 .save_proof_to_db(
                                     identifier,
@@ -48,10 +88,8 @@ impl Verifier {
                                     raw_string,
                                     poster_tx,
                                 )
-                                .await
-                                .map_err(|e| {
-                                    tracing::error!("Error saving proof to db {:?}", e.to_string());
-                                });
+                                .await;
+                            saved(log, result)
                         }
                         Err(e) => {
                             tracing::error!(
@@ -59,28 +97,128 @@ impl Verifier {
                                 identifier,
                                 e
                             );
+                            log.rejected(SubmissionOutcome::VerificationFailed, e)
                         }
                     }
                 }
                 ProofType::RISC0(_vec, identifier) => {
                     tracing::error!("RISC0 not supported! client={}", identifier);
+                    log.with_proof_type(&SupportedProvers::RISC0.to_string())
+                        .rejected(SubmissionOutcome::Unsupported, "RISC0 not supported")
                 }
                 ProofType::Dummy(vec, identifier) => {
                     tracing::warn!("Running dummy prover");
+                    let log = log.with_proof_type(&SupportedProvers::Dummy.to_string());
+                    let Some(height) = vec.get(1) else {
+                        tracing::error!("Dummy proof too short. client={}", identifier);
+                        self.db
+                            .log_submission(
+                                log.rejected(SubmissionOutcome::Malformed, "Dummy proof too short"),
+                            )
+                            .await;
+                        continue;
+                    };
+                    let height = *height as u64;
                     let proof_string = hex::encode(&vec);
-                    let _ = self
+                    let result = self
                         .db
                         .save_proof_to_db(
                             identifier,
                             SupportedProvers::Dummy,
-                            vec[1] as u64,
+                            height,
                             proof_string,
                             poster_tx,
                         )
                         .await;
+                    saved(log.with_height(height), result)
                 }
-            }
+            };
+            self.db.log_submission(log).await;
         }
         Ok(())
     }
 }
+
+/// Height in the first 8 bytes of the public values, as the proof claims it
+fn claimed_height(public_values: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        public_values.get(0..8)?.try_into().ok()?,
+    ))
+}
+
+/// Outcome of saving a verified proof
+fn saved(log: SubmissionLog, result: Result<ProofInsert>) -> SubmissionLog {
+    match result {
+        Ok(ProofInsert::Added { .. }) => log.with_outcome(SubmissionOutcome::Accepted),
+        Ok(ProofInsert::Duplicate) => log.with_outcome(SubmissionOutcome::Duplicate),
+        Err(e) => {
+            tracing::error!("Error saving proof to db {:?}", e.to_string());
+            log.rejected(SubmissionOutcome::Error, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::Verifier;
+    use crate::{
+        database::{
+            db::DB,
+            schema::{SubmissionLog, SubmissionOutcome},
+            storage::{Storage, SubmissionQuery},
+        },
+        types::ProofType,
+    };
+
+    fn dummy(proof: Vec<u8>, prover: &str) -> (ProofType, SubmissionLog) {
+        let log = SubmissionLog::received(None, hex::encode(&proof)).with_identifier(prover);
+        (ProofType::Dummy(proof, prover.to_string()), log)
+    }
+
+    #[tokio::test]
+    async fn test_threshold_reached_once() {
+        let db = Arc::new(DB::new(2, "memory://".to_string(), None).await);
+        let (verifier_tx, verifier_rx) = mpsc::channel(10);
+        let (poster_tx, mut poster_rx) = mpsc::channel(10);
+        let mut verifier = Verifier::new(verifier_rx, Arc::clone(&db));
+
+        for prover in ["prover-a", "prover-a", "prover-b", "prover-c"] {
+            verifier_tx
+                .send(dummy(vec![0, 5, 1], prover))
+                .await
+                .unwrap();
+        }
+        verifier_tx
+            .send(dummy(vec![0, 6, 1], "prover-a"))
+            .await
+            .unwrap();
+        drop(verifier_tx);
+        verifier.run(None, poster_tx).await.unwrap();
+
+        let params = poster_rx.recv().await.unwrap();
+        assert_eq!(params.height(), 5);
+        assert!(poster_rx.recv().await.is_none());
+        assert_eq!(db.unposted_batches(&[]).await.unwrap().len(), 1);
+
+        let outcomes: Vec<SubmissionOutcome> = db
+            .store
+            .submissions(SubmissionQuery {
+                identifier: Some("prover-a".to_string()),
+                height: Some(5),
+                limit: 10,
+            })
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| entry.outcome)
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![SubmissionOutcome::Duplicate, SubmissionOutcome::Accepted]
+        );
+    }
+}